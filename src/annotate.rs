@@ -0,0 +1,353 @@
+//! Rewrites the `//~` annotations of tests to match the errors rustc reports.
+//!
+//! This is meant for writing new tests and for updating existing ones after a toolchain update.
+//! Existing message patterns can also be converted to error code patterns, which don't change
+//! between toolchains, and new tests can be scaffolded by printing them with suggested annotations.
+//! Code and regular comments are left untouched: Only `//~` annotations are removed and inserted.
+
+use Config;
+use compile::Blueprint;
+use discover::Test;
+use error::{self, Error};
+use json::{Message, decode_output, parse_output};
+use normalize::Normalizer;
+use parse::{Matcher, MessageKind, TestExpectation};
+use report::describe_message;
+use runner::{compile_test, must_be_matched};
+use snapshot::unified_diff;
+
+use tempdir::TempDir;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Compiles a test and returns the (normalized) messages pointing into its files.
+pub fn compile_messages(blueprint: &Blueprint, test: &Test, expect: &TestExpectation, timeout: Option<Duration>, out_dir: &Path) -> Result<Vec<Message>, Error> {
+    let mut blueprint = blueprint.clone();
+    blueprint.set_out_dir(out_dir.to_owned());
+    let output = compile_test(&blueprint, &test.settings, &expect.entry_point, timeout)
+        .map_err(|e| Error::Compiler(format!("couldn't run rustc for {}: {}", test.name, e)))?
+        .ok_or_else(|| Error::Compiler(format!("compiling {} timed out", test.name)))?;
+
+    let json = decode_output(output.stderr)?;
+    let normalizer = Normalizer::new(&test.path, &test.settings, blueprint.sysroot())
+        .map_err(|e| Error::InvalidTest { path: test.path.clone(), message: e.to_string() })?;
+    let mut msgs = parse_output(&expect.files, &json)
+        .map_err(|e| Error::Compiler(format!("couldn't parse rustc output for {}: {}", test.name, e)))?;
+    for msg in &mut msgs {
+        msg.msg = normalizer.apply(&msg.msg);
+    }
+    Ok(msgs)
+}
+
+/// Compiles every test expecting errors and rewrites its annotations to match them.
+///
+/// If `dry_run` is set, the changes are printed as a diff instead of being written. Ignored tests,
+/// known bugs and tests that aren't expected to fail are skipped. `// fixed:` comments for lines
+/// that no longer have an error are dropped and reported.
+pub fn bless(config: &Config, blueprint: &Blueprint, tests: Vec<Test>, dry_run: bool) -> Result<(), Error> {
+    edit_tests(config, blueprint, tests, dry_run, |expect, file, content, msgs| {
+        let errors = msgs.iter()
+            .filter(|msg| msg.file == file && msg.kind == Some(MessageKind::Error))
+            .collect::<Vec<_>>();
+        for fix in expect.fixes.iter()
+            .filter(|fix| fix.file == file && !errors.iter().any(|msg| msg.line_num == fix.line_num)) {
+
+            println!("{}:{}: dropping `// fixed: {}`, since the line has no error anymore",
+                     file.display(), fix.line_num, fix.code);
+        }
+        rewrite(content, &errors)
+    })
+}
+
+/// Compiles every test expecting errors and turns its message patterns into error code patterns.
+///
+/// A pattern is only converted if it matches exactly one error, and no other error on the same
+/// line has the same code. All other message patterns are reported.
+pub fn upgrade(config: &Config, blueprint: &Blueprint, tests: Vec<Test>, dry_run: bool) -> Result<(), Error> {
+    edit_tests(config, blueprint, tests, dry_run, |expect, file, content, msgs| {
+        let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+        for pattern in expect.expected_msgs.iter()
+            .filter(|pattern| pattern.file == file && pattern.kind == Some(MessageKind::Error)) {
+
+            let message = match pattern.matcher {
+                Matcher::Msg(ref message) => message,
+                Matcher::Code(_) => continue,
+            };
+
+            let matching = msgs.iter().filter(|msg| pattern.matches(msg)).collect::<Vec<_>>();
+            let problem = match matching.len() {
+                0 => Some("it doesn't match any error".to_string()),
+                1 => match matching[0].code {
+                    Some(ref code) => {
+                        let same_code = msgs.iter()
+                            .filter(|msg| msg.file == pattern.file && msg.line_num == pattern.line_num)
+                            .filter(|msg| msg.kind == pattern.kind && msg.code.as_ref() == Some(code))
+                            .count();
+                        if same_code == 1 {
+                            let line = &mut lines[pattern.annotation_line - 1];
+                            *line = upgrade_annotation(line, code);
+                            None
+                        } else {
+                            Some(format!("{} other errors on line {} have the code {}", same_code - 1, pattern.line_num, code))
+                        }
+                    }
+                    None => Some("the error it matches has no code".to_string()),
+                },
+                n => Some(format!("it matches {} errors", n)),
+            };
+
+            if let Some(problem) = problem {
+                println!("{}:{}: can't convert `error: {}`: {}", file.display(), pattern.annotation_line, message, problem);
+            }
+        }
+
+        let mut upgraded = lines.join("\n");
+        if content.ends_with('\n') {
+            upgraded.push('\n');
+        }
+        upgraded
+    })
+}
+
+/// Compiles each of `tests` that expects errors and calls `edit` for each of its files.
+///
+/// `edit` gets the test's expectation, the file, its content and the compiler messages, and returns
+/// the new content. Changed files are written, or printed as a diff if `dry_run` is set.
+fn edit_tests<F>(config: &Config, blueprint: &Blueprint, tests: Vec<Test>, dry_run: bool, mut edit: F) -> Result<(), Error>
+where F: FnMut(&TestExpectation, &Path, &str, &[Message]) -> String {
+    let tempdir = TempDir::new("rust-compile-fail")?;
+    let mut num_changed = 0;
+
+    for (index, mut test) in tests.into_iter().enumerate() {
+        let expect = TestExpectation::parse(&test.path)
+            .map_err(|e| error::from_parse_error(&test.path, e))?;
+        test.settings.apply_config(config);
+        test.settings.apply(&expect.directives);
+        if !test.settings.mode.expects_errors()
+            || expect.directives.ignore.is_some()
+            || expect.directives.known_bug.is_some() {
+            info!("skipping {}", test.name);
+            continue;
+        }
+
+        let out_dir = tempdir.path().join(index.to_string());
+        fs::create_dir(&out_dir)?;
+        let timeout = expect.directives.timeout.or(config.timeout);
+        let msgs = compile_messages(blueprint, &test, &expect, timeout, &out_dir)?;
+        if !msgs.iter().any(|msg| msg.kind == Some(MessageKind::Error)) {
+            println!("warning: {} compiled without errors, leaving it unchanged", test.name);
+            continue;
+        }
+
+        for file in &expect.files {
+            let mut content = String::new();
+            File::open(file)?.read_to_string(&mut content)?;
+
+            let edited = edit(&expect, file, &content, &msgs);
+            if edited == content {
+                continue;
+            }
+
+            num_changed += 1;
+            if dry_run {
+                let name = file.display().to_string();
+                print!("{}", unified_diff(&name, &content, &name, &edited));
+            } else {
+                File::create(file)?.write_all(edited.as_bytes())?;
+                println!("updated {}", file.display());
+            }
+        }
+    }
+
+    println!("{} {} file{}", if dry_run { "would update" } else { "updated" },
+             num_changed, if num_changed == 1 { "" } else { "s" });
+    Ok(())
+}
+
+/// Compiles a single new test and returns it with suggested annotations for its errors.
+///
+/// Existing `//~` annotations are replaced. Warnings can't be matched by patterns, so a note asking
+/// to fix them is added for each warning that would fail the test. If the test compiles without
+/// errors, a warning is added, since it wouldn't be a useful compile-fail test.
+pub fn scaffold(config: &Config, blueprint: &Blueprint, mut test: Test) -> Result<String, Error> {
+    let tempdir = TempDir::new("rust-compile-fail")?;
+    let expect = TestExpectation::parse(&test.path)
+        .map_err(|e| error::from_parse_error(&test.path, e))?;
+    test.settings.apply_config(config);
+    test.settings.apply(&expect.directives);
+
+    let timeout = expect.directives.timeout.or(config.timeout);
+    let msgs = compile_messages(blueprint, &test, &expect, timeout, tempdir.path())?;
+
+    let mut out = String::new();
+    for file in &expect.files {
+        let mut content = String::new();
+        File::open(file)?.read_to_string(&mut content)?;
+
+        let errors = msgs.iter()
+            .filter(|msg| msg.file == *file && msg.kind == Some(MessageKind::Error))
+            .collect::<Vec<_>>();
+        if expect.files.len() > 1 {
+            out.push_str(&format!("--- {}\n", file.display()));
+        }
+        out.push_str(&rewrite(&content, &errors));
+    }
+
+    for warning in msgs.iter()
+        .filter(|msg| msg.kind == Some(MessageKind::Warning) && must_be_matched(msg, &test.settings)) {
+
+        out.push_str(&format!(
+            "note: {}:{}: {} (warnings can't be matched by patterns; fix it, or set `strict-warnings = false` in compile-fail.toml)\n",
+            warning.file.display(), warning.line_num, describe_message(warning)
+        ));
+    }
+    if !msgs.iter().any(|msg| msg.kind == Some(MessageKind::Error)) {
+        out.push_str(&format!("warning: {} compiled without errors, so it isn't a useful compile-fail test\n", test.name));
+    }
+    Ok(out)
+}
+
+/// Replaces the pattern in the `//~` annotation in `line` with one matching `code`.
+///
+/// The rest of the line, including the `^` or `|` that determine the line the pattern refers to,
+/// is kept.
+fn upgrade_annotation(line: &str, code: &str) -> String {
+    let start = match line.find("//~") {
+        Some(start) => start + "//~".len(),
+        None => return line.to_string(),
+    };
+    let rest = line[start..].trim_left_matches(|c| c == '|' || c == '^').trim_left();
+    format!("{}error[{}]", &line[..line.len() - rest.len()], code)
+}
+
+/// Returns the pattern for an error, preferring its code over its message.
+pub fn pattern_for(msg: &Message) -> String {
+    match msg.code {
+        Some(ref code) => format!("error[{}]", code),
+        None => format!("error: {}", msg.msg),
+    }
+}
+
+/// Replaces all `//~` annotations in `content` with ones matching `errors`.
+///
+/// Errors on a line that already had an annotation at its end get one there, to keep the style of
+/// the test. Otherwise, they are put on their own line below (`//~^`). Further errors on the same
+/// line are added as `//~|` annotations. `// fixed:` comments for lines without errors are removed,
+/// since a fix has to refer to a line with a pattern.
+pub fn rewrite(content: &str, errors: &[&Message]) -> String {
+    let mut out = String::new();
+    // Whether the last line of code (as opposed to comments) has errors
+    let mut last_code_has_errors = true;
+
+    for (i, line) in content.lines().enumerate() {
+        let annotation_start = line.find("//~");
+        if annotation_start.map_or(false, |start| line[..start].trim().is_empty()) {
+            // Annotation on its own line
+            continue;
+        }
+        let trimmed = line.trim();
+        if trimmed.starts_with("// fixed:") {
+            if !last_code_has_errors {
+                continue;
+            }
+        } else if !trimmed.is_empty() && !trimmed.starts_with("//") {
+            last_code_has_errors = errors.iter().any(|msg| msg.line_num == i + 1);
+        }
+
+        let mut patterns = errors.iter()
+            .filter(|msg| msg.line_num == i + 1)
+            .map(|msg| pattern_for(msg))
+            .collect::<Vec<_>>();
+        patterns.dedup();
+        let mut patterns = patterns.into_iter();
+        let indent = &line[..line.len() - line.trim_left().len()];
+
+        match annotation_start {
+            Some(start) => {
+                out.push_str(&line[..start]);
+                match patterns.next() {
+                    Some(pattern) => out.push_str(&format!("//~ {}", pattern)),
+                    None => {
+                        // Drop the whitespace separating the code from the old annotation
+                        let code_len = line[..start].trim_right().len();
+                        out.truncate(out.len() - (start - code_len));
+                    }
+                }
+            }
+            None => {
+                out.push_str(line);
+                if let Some(pattern) = patterns.next() {
+                    out.push_str(&format!("\n{}//~^ {}", indent, pattern));
+                }
+            }
+        }
+        out.push('\n');
+
+        for pattern in patterns {
+            out.push_str(&format!("{}//~| {}\n", indent, pattern));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_annotations() {
+        assert_eq!(upgrade_annotation("    let () = 0;  //~ error: mismatched types", "E0308"),
+                   "    let () = 0;  //~ error[E0308]");
+        assert_eq!(upgrade_annotation("    //~^^ ERROR:mismatched types", "E0308"),
+                   "    //~^^ error[E0308]");
+        assert_eq!(upgrade_annotation("//~| error: mismatched types", "E0308"),
+                   "//~| error[E0308]");
+    }
+
+    #[test]
+    fn suggests_patterns() {
+        assert_eq!(pattern_for(&Message::error(1, Some("E0308"), "mismatched types")), "error[E0308]");
+        assert_eq!(pattern_for(&Message::error(1, None, "expected one of `;` or `}`")), "error: expected one of `;` or `}`");
+    }
+
+    #[test]
+    fn rewrites_annotations() {
+        let errors = vec![
+            Message::error(3, Some("E0308"), "mismatched types"),
+            Message::error(5, None, "cannot find value `y` in this scope"),
+            Message::error(5, Some("E0425"), "cannot find value `z` in this scope"),
+            Message::error(7, Some("E0384"), "cannot assign twice to immutable variable `x`"),
+        ];
+        let errors = errors.iter().collect::<Vec<_>>();
+
+        assert_eq!(rewrite("\
+// A regular comment stays
+fn main() {
+    let () = 0;     //~ error: outdated message
+    // fixed: let () = ();
+    let x = y + z;
+    //~^ error[E0000]
+    x = 1;
+    let ok = 1; //~ error: this error went away
+    // fixed: let ok = 2;
+}
+", &errors), "\
+// A regular comment stays
+fn main() {
+    let () = 0;     //~ error[E0308]
+    // fixed: let () = ();
+    let x = y + z;
+    //~^ error: cannot find value `y` in this scope
+    //~| error[E0425]
+    x = 1;
+    //~^ error[E0384]
+    let ok = 1;
+}
+");
+    }
+}
@@ -0,0 +1,174 @@
+//! Parses the test selection and output arguments libtest understands.
+//!
+//! This allows narrowing down which compile-fail tests are run, eg. with
+//! `COMPILE_FAIL_FILTER=borrowck/ cargo test --test compile-fail`. Test binaries without the default
+//! harness (see `run_harness`) also take these arguments from the command line, eg.
+//! `cargo test --test harness -- borrowck/`. With the default harness, the command line belongs to
+//! libtest, whose filters select `#[test]` functions instead.
+
+use status::Format;
+
+use std::env;
+
+/// Name of the environment variable that can hold additional arguments.
+const FILTER_VAR: &'static str = "COMPILE_FAIL_FILTER";
+
+/// libtest options that take a value.
+const OPTIONS_WITH_VALUE: &'static [&'static str] = &[
+    "--logfile", "--color", "--shuffle-seed", "-Z",
+];
+
+/// Test selection and output arguments.
+#[derive(Debug, Default, PartialEq)]
+pub struct TestArgs {
+    /// Only tests whose name contains one of these are run. If empty, all tests are run.
+    pub filters: Vec<String>,
+    /// Tests whose name contains one of these are skipped.
+    pub skip: Vec<String>,
+    /// Require filters and skips to match the whole test name instead of a substring.
+    pub exact: bool,
+    /// Only list the tests instead of running them (`--list`).
+    pub list: bool,
+    /// Only run ignored tests (`--ignored`).
+    pub ignored: bool,
+    /// Run ignored tests along with all others (`--include-ignored`).
+    pub include_ignored: bool,
+    /// Output format (`--format`, `-q`).
+    pub format: Format,
+    /// Number of tests to run in parallel (`--test-threads`).
+    pub test_threads: Option<usize>,
+}
+
+impl TestArgs {
+    /// Collects the arguments passed to the test binary and those in `COMPILE_FAIL_FILTER`.
+    ///
+    /// Only binaries that don't use the default test harness may interpret their command line.
+    pub fn from_command_line() -> Self {
+        Self::from_env(env::args().skip(1).collect())
+    }
+
+    /// Collects the arguments in `COMPILE_FAIL_FILTER`.
+    pub fn from_filter_var() -> Self {
+        Self::from_env(Vec::new())
+    }
+
+    /// Parses `args`, followed by the arguments in `COMPILE_FAIL_FILTER`.
+    fn from_env(mut args: Vec<String>) -> Self {
+        if let Ok(var) = env::var(FILTER_VAR) {
+            args.extend(var.split_whitespace().map(str::to_string));
+        }
+
+        let args = Self::parse(args);
+        info!("test selection arguments: {:?}", args);
+        args
+    }
+
+    /// Parses libtest-style arguments. Unknown options are ignored.
+    pub fn parse<I>(args: I) -> Self
+    where I: IntoIterator<Item=String> {
+        let mut parsed = TestArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Split `--option=value` into option and value
+            let (option, value) = match arg.find('=') {
+                Some(eq) if arg.starts_with("--") => (&arg[..eq], Some(arg[eq+1..].to_string())),
+                _ => (&*arg, None),
+            };
+
+            if option == "--exact" {
+                parsed.exact = true;
+            } else if option == "--list" {
+                parsed.list = true;
+            } else if option == "--ignored" {
+                parsed.ignored = true;
+            } else if option == "--include-ignored" {
+                parsed.include_ignored = true;
+            } else if option == "-q" || option == "--quiet" {
+                parsed.format = Format::Terse;
+            } else if option == "--skip" {
+                parsed.skip.extend(value.or_else(|| args.next()));
+            } else if option == "--format" {
+                match value.or_else(|| args.next()).as_ref().map(|s| &**s) {
+                    Some("pretty") => parsed.format = Format::Pretty,
+                    Some("terse") => parsed.format = Format::Terse,
+                    Some("json") => parsed.format = Format::Json,
+                    other => warn!("unsupported output format {:?}", other),
+                }
+            } else if option == "--test-threads" {
+                parsed.test_threads = value.or_else(|| args.next()).and_then(|n| n.parse().ok());
+            } else if OPTIONS_WITH_VALUE.contains(&option) {
+                if value.is_none() {
+                    // Skip the value as well
+                    args.next();
+                }
+            } else if arg.starts_with('-') {
+                debug!("ignoring argument {}", arg);
+            } else {
+                parsed.filters.push(arg);
+            }
+        }
+
+        parsed
+    }
+
+    /// Determines whether the test called `name` should be run.
+    pub fn is_selected(&self, name: &str) -> bool {
+        let matches = |pattern: &String| if self.exact {
+            name == pattern
+        } else {
+            name.contains(&**pattern)
+        };
+
+        (self.filters.is_empty() || self.filters.iter().any(&matches))
+            && !self.skip.iter().any(&matches)
+    }
+
+    /// Whether tests marked with an `// ignore` directive should be run.
+    pub fn runs_ignored(&self) -> bool {
+        self.ignored || self.include_ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> TestArgs {
+        TestArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_args() {
+        assert_eq!(parse(&[]), TestArgs::default());
+        assert_eq!(parse(&["--test-threads", "4", "borrow", "--nocapture",
+                           "--skip", "slow", "--skip=huge", "--color=never", "--exact"]), TestArgs {
+            filters: vec!["borrow".to_string()],
+            skip: vec!["slow".to_string(), "huge".to_string()],
+            exact: true,
+            test_threads: Some(4),
+            ..TestArgs::default()
+        });
+        assert_eq!(parse(&["--list", "--format", "terse", "--ignored", "-Z", "unstable-options"]), TestArgs {
+            list: true,
+            ignored: true,
+            format: Format::Terse,
+            ..TestArgs::default()
+        });
+        assert!(parse(&["--include-ignored"]).include_ignored);
+        assert_eq!(parse(&["--format=json", "-q"]).format, Format::Terse);
+        assert_eq!(parse(&["-q", "--format=json"]).format, Format::Json);
+    }
+
+    #[test]
+    fn selects_tests() {
+        let args = parse(&["borrow", "--skip", "slow"]);
+        assert!(args.is_selected("borrowck/use-after-move.rs"));
+        assert!(!args.is_selected("borrowck/slow.rs"));
+        assert!(!args.is_selected("send-sync.rs"));
+
+        let args = parse(&["--exact", "borrowck/slow.rs"]);
+        assert!(args.is_selected("borrowck/slow.rs"));
+        assert!(!args.is_selected("borrowck/slow.rs.bak"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Compiles many tests with a single rustc invocation.
+//!
+//! Every rustc invocation pays for compiler start-up and for loading the metadata of all
+//! dependencies. In batch mode, tests are instead included as modules of a synthetic crate that is
+//! compiled once, and the diagnostics are split up by the file they point into.
+//!
+//! This is only an optimization: Tests that don't pass when compiled as part of a batch are
+//! compiled individually afterwards. This covers tests whose errors make rustc abort early (eg.
+//! parse or resolve errors), which hide the errors of other tests in the same batch. Tests that
+//! could pass in a batch but fail on their own are never batched (see `is_batchable`).
+
+use config::{Mode, TestSettings};
+use compile::Blueprint;
+use discover::Test;
+use parse::TestExpectation;
+use json::{decode_output, parse_output};
+use normalize::Normalizer;
+use process::output_with_timeout;
+use runner::compare_messages;
+use snapshot;
+
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Determines whether a test can be compiled as part of a batch.
+///
+/// Only single-file compile-fail tests are batched. Directory tests may contain crate-level
+/// attributes and module declarations that don't work when included as a module. Tests with their
+/// own timeout are excluded, since it can't be enforced for a single test in a batch. Ignored tests
+/// and known bugs don't need to pass, so batching them gains nothing. The order of errors in a batch
+/// differs from the one of a single test, so `strict_primary_error` can't be checked either, and
+/// neither can the rendered output in a `.stderr` snapshot.
+///
+/// A batch is a library with `dead_code` allowed, since the `main` functions of the tests are
+/// unused in it. Tests with `strict_warnings` would fail on their own because of `dead_code`
+/// warnings that a batch hides, and tests without a `main` because of the missing `main`.
+fn is_batchable(test: &Test, expect: &TestExpectation) -> bool {
+    test.settings.mode == Mode::CompileFail
+        && expect.files == [expect.entry_point.clone()]
+        && expect.directives.timeout.is_none()
+        && expect.directives.ignore.is_none()
+        && expect.directives.known_bug.is_none()
+        && !test.settings.strict_primary_error
+        && !snapshot::is_needed(&test.path)
+        && !test.settings.strict_warnings
+        && has_main(&test.path)
+}
+
+/// Whether the file at `path` defines a `main` function at the top level.
+///
+/// Items at the top level aren't indented, which is good enough to tell them apart from nested
+/// functions. Unreadable files count as not having a `main`.
+fn has_main(path: &Path) -> bool {
+    let mut content = String::new();
+    if File::open(path).and_then(|mut file| file.read_to_string(&mut content)).is_err() {
+        return false;
+    }
+    content.lines().any(|line| line.starts_with("fn main(") || line.starts_with("pub fn main("))
+}
+
+/// Compiles all batchable tests in `tests` in as few rustc invocations as possible.
+///
+/// Tests with different compiler settings are put in different batches. Returns the indices of
+/// all tests that passed. All other tests need to be compiled individually.
+///
+/// If compiling a batch takes longer than `timeout`, all tests in it are considered failed.
+pub fn run(blueprint: &Blueprint, timeout: Option<Duration>, out_dir: &Path, tests: &[(Test, TestExpectation)]) -> Result<Vec<usize>, Box<Error>> {
+    // Group tests by the settings affecting the compiler invocation
+    let mut batches: Vec<(&TestSettings, Vec<usize>)> = Vec::new();
+    for (index, &(ref test, ref expect)) in tests.iter().enumerate() {
+        if !is_batchable(test, expect) {
+            continue;
+        }
+
+        let settings = &test.settings;
+        match batches.iter_mut()
+            .find(|&&mut (s, _)| s.edition == settings.edition && s.flags == settings.flags) {
+            Some(&mut (_, ref mut indices)) => indices.push(index),
+            None => batches.push((settings, vec![index])),
+        }
+    }
+
+    let mut passed = Vec::new();
+    for (batch_index, (settings, indices)) in batches.into_iter().enumerate() {
+        let batch_dir = out_dir.join(format!("batch-{}", batch_index));
+        fs::create_dir(&batch_dir)?;
+        passed.extend(run_batch(blueprint, settings, timeout, &batch_dir, tests, &indices)?);
+    }
+
+    Ok(passed)
+}
+
+/// Compiles the tests at `indices` as one crate, returning the indices of the tests that passed.
+fn run_batch(blueprint: &Blueprint, settings: &TestSettings, timeout: Option<Duration>, batch_dir: &Path, tests: &[(Test, TestExpectation)], indices: &[usize]) -> Result<Vec<usize>, Box<Error>> {
+    info!("compiling {} tests as a batch in {}", indices.len(), batch_dir.display());
+
+    // `#[path]` is relative to the crate root, which lives in the output directory, so all test
+    // paths need to be absolute.
+    let cwd = env::current_dir()?;
+    let abs_paths = indices.iter()
+        .map(|&index| cwd.join(&tests[index].0.path))
+        .collect::<Vec<_>>();
+
+    let root = batch_dir.join("batch.rs");
+    {
+        let mut file = File::create(&root)?;
+        // Tests are usually binaries whose `main` would be unused in a module
+        writeln!(file, "#![allow(dead_code)]")?;
+        for (i, path) in abs_paths.iter().enumerate() {
+            writeln!(file, "#[path = {:?}]", path.to_str().ok_or("test path isn't valid UTF-8")?)?;
+            writeln!(file, "mod test_{};", i)?;
+        }
+    }
+
+    let mut blueprint = blueprint.clone();
+    blueprint.set_out_dir(batch_dir.to_owned());
+    let mut cmd = blueprint.build_command(&root, settings);
+    cmd.args(&["--crate-type", "lib", "--error-format", "json"]);
+    debug!("running {:?}", cmd);
+
+    let output = match output_with_timeout(&mut cmd, timeout)? {
+        Some(output) => output,
+        None => {
+            info!("batch timed out, compiling its tests individually");
+            return Ok(Vec::new());
+        }
+    };
+    let json = decode_output(output.stderr)?;
+
+    let mut passed = Vec::new();
+    for (&index, abs_path) in indices.iter().zip(&abs_paths) {
+        let (ref test, ref expect) = tests[index];
+        let mut msgs = parse_output(&[abs_path.clone()], &json)?;
+        let normalizer = Normalizer::new(&test.path, &test.settings, blueprint.sysroot())?;
+        for msg in &mut msgs {
+            // Make the messages point at the path the patterns use
+            msg.file = test.path.clone();
+            msg.msg = normalizer.apply(&msg.msg);
+        }
+
+        match compare_messages(&expect.expected_msgs, &msgs, &test.settings) {
+            // Ambiguous patterns are only reported for tests compiled individually
+            Ok(ref comparison) if comparison.is_match() && comparison.ambiguous.is_empty() => passed.push(index),
+            Ok(comparison) => info!("test {} failed in batch, compiling it individually:\n{}", test.name, comparison),
+            Err(e) => info!("test {} failed in batch, compiling it individually: {}", test.name, e),
+        }
+    }
+
+    info!("{} of {} batched tests passed", passed.len(), indices.len());
+    Ok(passed)
+}
@@ -1,15 +1,20 @@
 use Config;
+use config::{Mode, TestSettings};
+use error;
 
 use build_plan::{BuildPlan, TargetKind};
+use serde_json;
+use std::env;
 use std::error::Error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
 use std::process::Command;
 use std::path::{Path, PathBuf};
 
 /// Commandline invocation blueprint for compiling tests like Cargo would.
 ///
 /// This is obtained once at the start by hooking into Cargo.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blueprint {
     /// Compiler executable.
     program: String,
@@ -17,13 +22,30 @@ pub struct Blueprint {
     args: Vec<OsString>,
     /// Index in `args` to replace with the source file we want to compile.
     source_file_index: usize,
+    /// The compiler's sysroot, if it could be determined.
+    sysroot: Option<PathBuf>,
 
+    #[serde(skip)]
     out_dir: Option<PathBuf>,
+    /// Whether to only emit metadata for compile-fail tests.
+    #[serde(skip)]
+    check_only: bool,
+}
+
+/// A `Blueprint` stored on disk, along with the wrapper test it was obtained for.
+#[derive(Serialize, Deserialize)]
+struct CachedBlueprint {
+    wrapper_test: String,
+    blueprint: Blueprint,
 }
 
 impl Blueprint {
     /// Obtains a `Blueprint` by attempting to compile the wrapper test with Cargo.
-    pub fn obtain(config: &Config) -> Result<Self, Box<Error>> {
+    pub fn obtain(config: &Config) -> Result<Self, error::Error> {
+        Self::from_build_plan(config).map_err(|e| error::Error::Blueprint(e.to_string()))
+    }
+
+    fn from_build_plan(config: &Config) -> Result<Self, Box<Error>> {
         // FIXME make `env!("CARGO")` configurable
         let output = Command::new(env!("CARGO"))
             .arg("-Zunstable-options")
@@ -41,7 +63,15 @@ impl Blueprint {
 
         let plan = BuildPlan::from_cargo_output(raw_plan)?;
         let invocations = plan.invocations.iter().filter(|inv| inv.target_kind == TargetKind::Test).collect::<Vec<_>>();
-        assert_eq!(invocations.len(), 1);
+        if invocations.len() != 1 {
+            return Err(format!(
+                "expected exactly one test invocation in Cargo's build plan, found {}{}",
+                invocations.len(),
+                invocations.iter()
+                    .map(|inv| format!("\n  {} {}", inv.program, inv.args.join(" ")))
+                    .collect::<String>()
+            ).into());
+        }
         let invocation = invocations[0];
 
         // Extract arguments, replacing the arg containing `compile-fail.rs` with whatever we want
@@ -73,7 +103,7 @@ impl Blueprint {
                 return Err(format!(
                     "found multiple arguments containing the wrapper test path in compiler command line: {}",
                     matches.iter()
-                        .map(|&(i, arg)| format!("argument #{} ({})", i + 1, arg.to_str().unwrap()))
+                        .map(|&(i, arg)| format!("argument #{} ({})", i + 1, arg.to_string_lossy()))
                         .collect::<Vec<_>>()
                         .join(", ")
                 ).into());
@@ -82,36 +112,192 @@ impl Blueprint {
             matches[0].0
         };
 
+        let sysroot = Command::new(&invocation.program)
+            .args(&["--print", "sysroot"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()));
+        debug!("compiler sysroot: {:?}", sysroot);
+
         Ok(Blueprint {
             program: invocation.program.clone(),
             args,
             source_file_index,
+            sysroot,
             out_dir: None,
+            check_only: false,
         })
     }
 
+    /// Like `obtain`, but caches the `Blueprint` in a file next to the running test binary.
+    ///
+    /// Tools like `cargo nextest` start the test binary once for every test. Asking Cargo for a
+    /// build plan each time would be slow, so processes load the blueprint stored by an earlier one
+    /// instead. There is no lock around obtaining and storing it, so processes that start before the
+    /// first one stored it still ask Cargo themselves. The cache is invalidated whenever the test
+    /// binary is rebuilt, which Cargo does whenever anything that could influence the build plan
+    /// changes.
+    pub fn obtain_cached(config: &Config) -> Result<Self, error::Error> {
+        let cache_path = match cache_path() {
+            Some(path) => path,
+            None => return Self::obtain(config),
+        };
+
+        match Self::load_cache(&cache_path, config) {
+            Ok(Some(blueprint)) => {
+                info!("using cached blueprint from {}", cache_path.display());
+                return Ok(blueprint);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("couldn't load cached blueprint from {}: {}", cache_path.display(), e),
+        }
+
+        let blueprint = Self::obtain(config)?;
+        if let Err(e) = blueprint.store_cache(&cache_path, config) {
+            warn!("couldn't cache blueprint in {}: {}", cache_path.display(), e);
+        }
+        Ok(blueprint)
+    }
+
+    /// Loads the cached blueprint at `path` if it is still valid.
+    fn load_cache(path: &Path, config: &Config) -> Result<Option<Self>, Box<Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let exe_modified = fs::metadata(env::current_exe()?)?.modified()?;
+        if fs::metadata(path)?.modified()? < exe_modified {
+            info!("cached blueprint at {} is outdated", path.display());
+            return Ok(None);
+        }
+
+        let cached: CachedBlueprint = serde_json::from_reader(File::open(path)?)?;
+        if cached.wrapper_test != config.wrapper_test {
+            return Ok(None);
+        }
+        Ok(Some(cached.blueprint))
+    }
+
+    fn store_cache(&self, path: &Path, config: &Config) -> Result<(), Box<Error>> {
+        // Write to a temporary file first, so that concurrently running tests never read a
+        // partially written cache
+        let tmp_path = path.with_file_name(format!(
+            "{}.{}", path.file_name().unwrap().to_string_lossy(), ::std::process::id()
+        ));
+        serde_json::to_writer(File::create(&tmp_path)?, &CachedBlueprint {
+            wrapper_test: config.wrapper_test.to_string(),
+            blueprint: self.clone(),
+        })?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn sysroot(&self) -> Option<&Path> {
+        self.sysroot.as_ref().map(|path| &**path)
+    }
+
     pub fn set_out_dir(&mut self, out_dir: PathBuf) {
         self.out_dir = Some(out_dir);
     }
 
+    /// If set to `true`, tests in `CompileFail` mode will only be type-checked, skipping codegen.
+    ///
+    /// Tests in `CheckPass` mode are always only type-checked.
+    pub fn set_check_only(&mut self, check_only: bool) {
+        self.check_only = check_only;
+    }
+
     /// Builds a `Command` that invokes rustc to compile the file `source`.
-    pub fn build_command(&self, source: &Path) -> Command {
+    ///
+    /// The edition and additional flags from `settings` are applied to the command.
+    pub fn build_command(&self, source: &Path, settings: &TestSettings) -> Command {
+        let check_only = settings.mode == Mode::CheckPass
+            || (self.check_only && settings.mode == Mode::CompileFail);
         let mut cmd = Command::new(&self.program);
         let mut out_dir = false;
+        let mut emit = false;
+        let mut edition = false;
         cmd.args(self.args.iter()
             .enumerate()
+            .filter(|&(_, arg)| {
+                // Drop Cargo's `--edition` if the settings specify a different one
+                if settings.edition.is_none() {
+                    return true;
+                }
+                if edition {
+                    edition = false;
+                    return false;
+                }
+                if arg == "--edition" {
+                    edition = true;
+                    return false;
+                }
+                !arg.to_string_lossy().starts_with("--edition=")
+            })
             .map(|(i, arg)| if i == self.source_file_index {
                 source.as_os_str()
             } else if out_dir && self.out_dir.is_some() {
                 out_dir = false;
                 self.out_dir.as_ref().unwrap().as_os_str()
+            } else if emit && check_only {
+                emit = false;
+                OsStr::new("metadata")
+            } else if check_only && arg.to_string_lossy().starts_with("--emit=") {
+                OsStr::new("--emit=metadata")
             } else {
                 if arg == "--out-dir" {
                     out_dir = true;
                 }
+                if arg == "--emit" {
+                    emit = true;
+                }
                 arg.as_os_str()
             })
         );
+        if let Some(ref edition) = settings.edition {
+            cmd.arg(format!("--edition={}", edition));
+        }
+        cmd.args(&settings.flags);
         cmd
     }
+
+    /// Returns the path of the executable produced by a command from `build_command`.
+    ///
+    /// rustc names it after the crate name and the suffix (`-C extra-filename`) passed by Cargo.
+    pub fn executable_path(&self) -> Result<PathBuf, Box<Error>> {
+        let out_dir = self.out_dir.as_ref().ok_or("no output directory set")?;
+        let mut crate_name = None;
+        let mut extra_filename = String::new();
+        let mut args = self.args.iter().map(|arg| arg.to_string_lossy());
+        while let Some(arg) = args.next() {
+            if arg == "--crate-name" {
+                crate_name = args.next().map(|name| name.into_owned());
+                continue;
+            }
+
+            let codegen_option = if arg == "-C" {
+                args.next()
+            } else if arg.starts_with("-C") {
+                Some(arg[2..].to_string().into())
+            } else {
+                None
+            };
+            if let Some(option) = codegen_option {
+                if option.starts_with("extra-filename=") {
+                    extra_filename = option["extra-filename=".len()..].to_string();
+                }
+            }
+        }
+
+        let crate_name = crate_name.ok_or("couldn't find crate name in compiler command line")?;
+        Ok(out_dir.join(format!("{}{}{}", crate_name, extra_filename, env::consts::EXE_SUFFIX)))
+    }
+}
+
+/// Returns the path of the blueprint cache of the running test binary.
+fn cache_path() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    let file_name = format!("{}.compile-fail-blueprint.json", exe.file_name()?.to_string_lossy());
+    Some(exe.with_file_name(file_name))
 }
@@ -1,12 +1,35 @@
-use std::path::PathBuf;
+use parse::Directives;
+
+use toml;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Test runner configuration.
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// Path to the directory containing the compile-fail tests.
+    /// Paths to the directories containing the compile-fail tests.
+    ///
+    /// Each directory may contain a `compile-fail.toml` file specifying defaults for the tests in
+    /// it (see the crate-level documentation).
+    ///
+    /// By default, only `tests/compile-fail` is searched.
+    pub cfail_paths: Vec<PathBuf>,
+
+    /// Glob patterns selecting which tests to run (eg. `borrowck/*`).
+    ///
+    /// Patterns are matched against the name of a test, which is its path relative to the test
+    /// directory, using `/` as the separator. When multiple test directories are configured, the
+    /// name is prefixed with the directory's name. If empty, all tests are included.
+    pub include: Vec<String>,
+
+    /// Glob patterns of tests and directories to skip, matched like `include`.
     ///
-    /// By default, `tests/compile-fail` is searched.
-    pub cfail_path: PathBuf,
+    /// Exclusion takes precedence over inclusion.
+    pub exclude: Vec<String>,
 
     /// Path to the integration test invoking the `compile-fail` runner.
     ///
@@ -17,17 +40,201 @@ pub struct Config {
 
     /// If this is set to `true`, no output will be printed directly to the console.
     ///
-    /// Note that logging can still write to the console, if enabled.
+    /// Note that logging can still write to the console, if enabled. Warnings about tests that are
+    /// otherwise printed with the results (eg. ambiguous patterns) are logged instead.
     pub no_console_output: bool,
+
+    /// Number of tests to compile in parallel.
+    ///
+    /// If `None`, the `RUST_TEST_THREADS` environment variable is used if set, and the number of
+    /// CPUs otherwise.
+    pub jobs: Option<usize>,
+
+    /// Compile tests in batches to speed up large test suites.
+    ///
+    /// In batch mode, single-file tests sharing the same compiler settings are included as modules
+    /// in a single crate that is compiled once. Tests that don't pass as part of a batch are
+    /// recompiled on their own. Only tests with `strict-warnings = false` and a `main` function
+    /// are batched, since `dead_code` warnings and the missing `main` error can't be checked in a
+    /// batch.
+    ///
+    /// Defaults to `false`.
+    pub batch: bool,
+
+    /// Only type-check tests instead of fully building them.
+    ///
+    /// This passes `--emit=metadata` to rustc, which skips code generation and linking. Tests
+    /// whose expected errors are only emitted in those stages can opt out using a `// build-fail`
+    /// directive.
+    ///
+    /// Defaults to `false`.
+    pub check_only: bool,
+
+    /// Time limit for compiling a single test.
+    ///
+    /// If the compiler takes longer, it is killed (along with its child processes) and the test
+    /// fails. Tests can override this with a `// timeout: 60s` directive.
+    ///
+    /// Defaults to `None` (no time limit).
+    pub timeout: Option<Duration>,
+
+    /// Verify that every error pattern is caused by the line it points at.
+    ///
+    /// For each line with a pattern, a variant of the test is compiled in which the line is
+    /// replaced by the code in a `// fixed: <code>` comment below it, or removed if there is none.
+    /// The errors matched by the line's patterns must disappear in the variant. This catches tests
+    /// that only pass because of an unrelated error on the same line. A variant with errors that
+    /// no pattern matches fails as well, since rustc might have stopped before the line's errors.
+    ///
+    /// This compiles every test once more per annotated line and disables `batch`. Defaults to
+    /// `false`.
+    pub verify_fixes: bool,
+
+    /// Require the first error emitted by rustc to be matched by the first pattern of each test.
+    ///
+    /// This also checks that rustc's "aborting due to N previous errors" summary agrees with the
+    /// number of errors found in the test's files, and prints a warning with the test results if it
+    /// doesn't. Can be enabled for single tests with a `// strict-primary-error` directive or in
+    /// `compile-fail.toml`.
+    ///
+    /// Defaults to `false`.
+    pub strict_primary_error: bool,
+
+    /// Regex replacements applied to compiler output, as `(regex, replacement)` pairs.
+    ///
+    /// They are applied to the messages patterns are matched against and to the rendered output
+    /// compared with `.stderr` snapshots, after the built-in normalizations (which replace the
+    /// test's directory with `$DIR`, remove crate hashes and replace the path of the Rust sources
+    /// with `$SRC_DIR`). The replacement can refer to capture groups (eg. `$1`).
+    ///
+    /// Tests can add more replacements in `compile-fail.toml` or with a
+    /// `// normalize-stderr: "regex" -> "replacement"` directive.
+    pub normalize: Vec<(String, String)>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            cfail_path: PathBuf::from("tests/compile-fail"),
+            cfail_paths: vec![PathBuf::from("tests/compile-fail")],
+            include: Vec::new(),
+            exclude: Vec::new(),
             // This default will be overwritten by the `run_compile_fail_tests!` macro, which passes `file!()`.
             wrapper_test: "tests/compile-fail.rs",
             no_console_output: false,
+            jobs: None,
+            batch: false,
+            check_only: false,
+            timeout: None,
+            verify_fixes: false,
+            strict_primary_error: false,
+            normalize: Vec::new(),
+        }
+    }
+}
+
+/// Name of the file in a test directory that specifies defaults for its tests.
+const SETTINGS_FILE: &'static str = "compile-fail.toml";
+
+/// Determines what is expected of a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// The test must fail to compile with the errors specified by its patterns.
+    CompileFail,
+    /// Like `CompileFail`, but the test is always fully built, even if `Config::check_only` is set.
+    ///
+    /// This is needed for errors that are only emitted during monomorphization or linking.
+    BuildFail,
+    /// The test must type-check without errors. Codegen is skipped.
+    CheckPass,
+    /// The test must build without errors, and the resulting executable must exit successfully.
+    RunPass,
+    /// The test must build without errors, and the resulting executable must fail (eg. panic).
+    RunFail,
+}
+
+impl Mode {
+    /// Whether tests in this mode must fail to compile, and thus need error patterns.
+    pub fn expects_errors(&self) -> bool {
+        match *self {
+            Mode::CompileFail | Mode::BuildFail => true,
+            Mode::CheckPass | Mode::RunPass | Mode::RunFail => false,
+        }
+    }
+}
+
+/// Settings applying to every test in a test directory, read from its `compile-fail.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct TestSettings {
+    /// Rust edition to compile the tests with, overriding the one Cargo uses.
+    pub edition: Option<String>,
+    /// Additional flags to pass to rustc.
+    pub flags: Vec<String>,
+    /// Whether every warning emitted by rustc must be matched by a pattern. If `false`, unmatched
+    /// warnings are ignored.
+    pub strict_warnings: bool,
+    /// Whether the first error emitted by rustc must be matched by the first pattern of the test.
+    ///
+    /// Cascading errors can hide that the actual cause of a failure changed. Can also be enabled
+    /// with `Config::strict_primary_error` or a `// strict-primary-error` directive.
+    pub strict_primary_error: bool,
+    /// Regex replacements applied to compiler messages and rendered output, as
+    /// `(regex, replacement)` pairs (see `Config::normalize`).
+    pub normalize: Vec<(String, String)>,
+    /// What is expected of the tests (eg. `mode = "run-pass"`). Single tests can override it with a
+    /// directive like `// run-pass`.
+    pub mode: Mode,
+}
+
+impl Default for TestSettings {
+    fn default() -> Self {
+        TestSettings {
+            edition: None,
+            flags: Vec::new(),
+            strict_warnings: true,
+            strict_primary_error: false,
+            normalize: Vec::new(),
+            mode: Mode::CompileFail,
+        }
+    }
+}
+
+impl TestSettings {
+    /// Loads the settings for the test directory `dir`.
+    ///
+    /// If `dir` contains no `compile-fail.toml`, the default settings are returned.
+    pub fn load(dir: &Path) -> Result<Self, Box<Error>> {
+        let path = dir.join(SETTINGS_FILE);
+        if !path.is_file() {
+            return Ok(TestSettings::default());
+        }
+
+        let mut content = String::new();
+        File::open(&path)?.read_to_string(&mut content)?;
+        let settings = toml::from_str(&content)
+            .map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+        info!("loaded test settings from {}: {:?}", path.display(), settings);
+        Ok(settings)
+    }
+
+    /// Applies the settings from `config` that can be overridden per test directory.
+    pub fn apply_config(&mut self, config: &Config) {
+        self.strict_primary_error |= config.strict_primary_error;
+        // The rules from `config` come first, followed by the ones from `compile-fail.toml`
+        let rules = self.normalize.drain(..).collect::<Vec<_>>();
+        self.normalize = config.normalize.iter().cloned().chain(rules).collect();
+    }
+
+    /// Applies the directives of a single test, which take precedence over the directory's
+    /// defaults.
+    pub fn apply(&mut self, directives: &Directives) {
+        if let Some(mode) = directives.mode {
+            self.mode = mode;
+        }
+        if directives.strict_primary_error {
+            self.strict_primary_error = true;
         }
+        self.normalize.extend(directives.normalize.iter().cloned());
     }
 }
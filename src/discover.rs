@@ -0,0 +1,185 @@
+//! Locates compile-fail tests on disk.
+
+use Config;
+use config::TestSettings;
+use error;
+use parse;
+
+use glob::Pattern as Glob;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A compile-fail test found on disk.
+#[derive(Debug, Clone)]
+pub struct Test {
+    /// Name of the test, derived from its path relative to the test directory (eg.
+    /// `borrowck/use-after-move.rs`). Always uses `/` as the path separator.
+    pub name: String,
+    /// Path to the `.rs` file or directory making up the test.
+    pub path: PathBuf,
+    /// Settings of the test directory the test was found in. Once the test is parsed, its
+    /// directives are applied to these.
+    pub settings: TestSettings,
+}
+
+impl Test {
+    /// Creates a `Test` for a path that wasn't found via discovery, naming it after the path.
+    ///
+    /// The settings are loaded from the directory containing `path`.
+    pub fn from_path(path: PathBuf) -> Result<Self, error::Error> {
+        let settings = TestSettings::load(path.parent().unwrap_or(Path::new(".")))
+            .map_err(|e| error::Error::Discovery(e.to_string()))?;
+        Ok(Test {
+            name: path.display().to_string(),
+            path,
+            settings,
+        })
+    }
+}
+
+/// Include and exclude patterns from the `Config`, compiled to globs.
+struct Filter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl Filter {
+    fn new(config: &Config) -> Result<Self, Box<Error>> {
+        fn compile(patterns: &[String]) -> Result<Vec<Glob>, Box<Error>> {
+            patterns.iter()
+                .map(|pat| Glob::new(pat).map_err(|e| format!("invalid glob '{}': {}", pat, e).into()))
+                .collect()
+        }
+
+        Ok(Filter {
+            include: compile(&config.include)?,
+            exclude: compile(&config.exclude)?,
+        })
+    }
+
+    fn is_excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|glob| glob.matches(name))
+    }
+
+    fn is_included(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(name)))
+            && !self.is_excluded(name)
+    }
+}
+
+/// Recursively locates compile-fail tests in the configured directories (`tests/compile-fail` by
+/// default).
+///
+/// Every `.rs` file is a test, as is every directory containing a `main.rs` or `lib.rs`. All other
+/// directories are searched for more tests. The returned tests are sorted by name.
+pub fn find_tests(config: &Config) -> Result<Vec<Test>, error::Error> {
+    locate_tests(config).map_err(|e| error::Error::Discovery(e.to_string()))
+}
+
+fn locate_tests(config: &Config) -> Result<Vec<Test>, Box<Error>> {
+    info!("searching for compile-fail tests, config = {:?}", config);
+
+    let filter = Filter::new(config)?;
+    let mut tests = Vec::new();
+    for root in &config.cfail_paths {
+        // Only prefix test names with the directory name if they could be ambiguous otherwise.
+        let prefix = if config.cfail_paths.len() > 1 {
+            root.file_name().map(|name| name.to_string_lossy().into_owned())
+        } else {
+            None
+        };
+        let root = Root {
+            path: root,
+            prefix,
+            settings: TestSettings::load(root)?,
+        };
+
+        visit_dir(&root, &root.path, &filter, &mut tests)?;
+    }
+
+    // As a safeguard, raise an error when no test was found. This often indicates that a wrong
+    // directory was specified.
+    if tests.is_empty() {
+        return Err(format!(
+            "no compile-fail test found in {}",
+            config.cfail_paths.iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ).into());
+    }
+
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Some(dup) = tests.windows(2).find(|pair| pair[0].name == pair[1].name) {
+        return Err(format!(
+            "found multiple tests named '{}' ({} and {})",
+            dup[0].name, dup[0].path.display(), dup[1].path.display()
+        ).into());
+    }
+
+    Ok(tests)
+}
+
+/// A configured test directory.
+struct Root<'a> {
+    path: &'a Path,
+    /// Prefix of the names of all tests in this directory.
+    prefix: Option<String>,
+    settings: TestSettings,
+}
+
+fn visit_dir(root: &Root, dir: &Path, filter: &Filter, tests: &mut Vec<Test>) -> Result<(), Box<Error>> {
+    for entry in fs::read_dir(dir)
+        .map_err(|e| format!("couldn't open {}: {}", dir.display(), e))? {
+
+        let path = entry?.path();
+        let name = test_name(root, &path);
+
+        if path.is_dir() {
+            if filter.is_excluded(&name) {
+                debug!("skipping excluded directory {}", path.display());
+            } else if parse::entry_point(&path).is_some() {
+                // A directory containing a `main.rs` or `lib.rs` forms a single multi-file test.
+                if filter.is_included(&name) {
+                    info!("found compile-fail test directory at {}", path.display());
+                    tests.push(Test { name, path, settings: root.settings.clone() });
+                }
+            } else {
+                visit_dir(root, &path, filter, tests)?;
+            }
+            continue;
+        }
+
+        if path.extension().map_or(true, |ext| ext != "rs") {
+            // Only consider `.rs` files, skipping things like a `README.md` or `.gitkeep`.
+            debug!("skipping non-Rust file {}", path.display());
+            continue;
+        }
+
+        if !path.is_file() {
+            return Err(format!(
+                "unsupported file type of compile-fail test '{}'", path.display()
+            ).into());
+        }
+
+        if filter.is_included(&name) {
+            info!("found compile-fail test at {}", path.display());
+            tests.push(Test { name, path, settings: root.settings.clone() });
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the name of the test at `path` from its path relative to `root`.
+fn test_name(root: &Root, path: &Path) -> String {
+    root.prefix.iter()
+        .cloned()
+        .chain(path.strip_prefix(root.path)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,143 @@
+//! The error type returned by the public functions of this crate.
+
+use parse::{AnnotationError, AnnotationErrors};
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while running compile-fail tests.
+#[derive(Debug)]
+pub enum Error {
+    /// The compiler invocation for the tests couldn't be obtained from Cargo.
+    Blueprint(String),
+    /// The tests couldn't be located (eg. because a test directory doesn't exist or contains no
+    /// tests, or because of an invalid glob in `Config::include`).
+    Discovery(String),
+    /// A test contains malformed `//~` annotations.
+    ///
+    /// Only returned by functions working on a single test (eg. `scaffold`). When running tests,
+    /// they are reported in `TestFailure::annotation_errors` instead.
+    Annotations(Vec<AnnotationError>),
+    /// A test is invalid for another reason (eg. a malformed directive, or a compile-fail test
+    /// without patterns).
+    InvalidTest {
+        /// Path to the test.
+        path: PathBuf,
+        /// Description of the problem, including its location.
+        message: String,
+    },
+    /// The compiler couldn't be run, or its output couldn't be parsed.
+    Compiler(String),
+    /// At least one test failed.
+    TestsFailed(Vec<TestFailure>),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Blueprint(ref msg) => write!(f, "couldn't obtain the compiler invocation: {}", msg),
+            Error::Discovery(ref msg) => write!(f, "{}", msg),
+            Error::Annotations(ref errors) => write!(f, "{}", AnnotationErrors(errors.clone())),
+            Error::InvalidTest { ref message, .. } => write!(f, "{}", message),
+            Error::Compiler(ref msg) => write!(f, "{}", msg),
+            Error::TestsFailed(ref failures) => {
+                for failure in failures {
+                    writeln!(f, "---- test {} ----", failure.name)?;
+                    writeln!(f, "{}", failure.message)?;
+                    writeln!(f)?;
+                }
+                write!(f, "{} compile-fail tests failed", failures.len())
+            }
+            Error::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Blueprint(_) => "couldn't obtain the compiler invocation",
+            Error::Discovery(_) => "couldn't locate the tests",
+            Error::Annotations(_) => "invalid annotations",
+            Error::InvalidTest { .. } => "invalid test",
+            Error::Compiler(_) => "couldn't run the compiler",
+            Error::TestsFailed(_) => "compile-fail tests failed",
+            Error::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A test that failed, as reported by `Error::TestsFailed`.
+#[derive(Debug, Clone)]
+pub struct TestFailure {
+    /// Name of the test (eg. `borrowck/use-after-move.rs`).
+    pub name: String,
+    /// Description of the failure, as printed in the test report.
+    pub message: String,
+    /// The patterns and compiler messages that didn't match. Empty if the test failed for another
+    /// reason (eg. a timeout).
+    pub mismatches: Vec<Mismatch>,
+    /// The malformed `//~` annotations that kept the test from running. Empty if the test could be
+    /// parsed.
+    pub annotation_errors: Vec<AnnotationError>,
+}
+
+/// A difference between the patterns of a test and the messages reported by rustc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// A pattern didn't match any message.
+    Missing {
+        file: PathBuf,
+        /// The line the pattern points at.
+        line: usize,
+        /// The pattern as written in the test (eg. `error[E0308]`).
+        pattern: String,
+    },
+    /// An error (or a warning, if `strict-warnings` is enabled) wasn't matched by any pattern.
+    Unexpected {
+        file: PathBuf,
+        line: usize,
+        /// The message as reported by rustc (eg. `error[E0308]: mismatched types`).
+        message: String,
+    },
+    /// The first error wasn't matched by the first pattern (see `Config::strict_primary_error`).
+    WrongPrimaryError {
+        file: PathBuf,
+        line: usize,
+        /// The first error reported by rustc.
+        message: String,
+        /// The first pattern of the test.
+        pattern: String,
+    },
+    /// A pattern matched more than one message.
+    ///
+    /// This doesn't make a test fail on its own, since rustc sometimes emits the same error twice.
+    Ambiguous {
+        file: PathBuf,
+        line: usize,
+        pattern: String,
+        /// The messages the pattern matched, as reported by rustc.
+        messages: Vec<String>,
+    },
+}
+
+/// Converts an error returned by `TestExpectation::parse` for the test at `path`.
+pub fn from_parse_error(path: &Path, e: Box<StdError>) -> Error {
+    match e.downcast::<AnnotationErrors>() {
+        Ok(errors) => Error::Annotations(errors.0),
+        Err(e) => Error::InvalidTest {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        },
+    }
+}
@@ -10,10 +10,11 @@
 
 // Adapted from `compiletest-rs`.
 
+use error;
 use parse::MessageKind;
 use serde_json as json;
 use std::str::FromStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 
 #[derive(Debug)]
@@ -26,8 +27,28 @@ pub struct Message {
     pub msg: String,
     /// The code of the error this message is a part of.
     pub code: Option<String>,
+    /// The file the message points into.
+    pub file: PathBuf,
     /// The line at which the message points.
     pub line_num: usize,
+    /// The whole diagnostic as rendered by rustc, if this is the first line of a top-level
+    /// diagnostic.
+    pub rendered: Option<String>,
+}
+
+#[cfg(test)]
+impl Message {
+    /// Creates an error pointing at line `line_num` of `test.rs`, for use in tests.
+    pub fn error(line_num: usize, code: Option<&str>, msg: &str) -> Self {
+        Message {
+            kind: Some(MessageKind::Error),
+            msg: msg.to_string(),
+            code: code.map(str::to_string),
+            file: PathBuf::from("test.rs"),
+            line_num,
+            rendered: None,
+        }
+    }
 }
 
 // These structs are a subset of the ones found in
@@ -87,10 +108,16 @@ struct DiagnosticCode {
     explanation: Option<String>,
 }
 
-pub fn parse_output(file_name: &str, output: &str) -> Result<Vec<Message>, Box<Error>> {
+/// Decodes the output rustc wrote to stderr.
+pub fn decode_output(stderr: Vec<u8>) -> Result<String, error::Error> {
+    String::from_utf8(stderr).map_err(|_| error::Error::Compiler("rustc output wasn't UTF-8".to_string()))
+}
+
+/// Parses rustc's JSON output, keeping only messages pointing into one of `files`.
+pub fn parse_output(files: &[PathBuf], output: &str) -> Result<Vec<Message>, Box<Error>> {
     // this probably wants `try_fold`
     output.lines()
-        .map(|line| parse_line(file_name, line))
+        .map(|line| parse_line(files, line))
         .fold(Ok(vec![]), |state, result| {
             state.and_then(|mut msgs| result.map(|mut new_msgs| {
                 msgs.append(&mut new_msgs);
@@ -99,13 +126,43 @@ pub fn parse_output(file_name: &str, output: &str) -> Result<Vec<Message>, Box<E
         })
 }
 
-fn parse_line(file_name: &str, line: &str) -> Result<Vec<Message>, Box<Error>> {
+/// Concatenates the human-readable form of all diagnostics in rustc's JSON `output`.
+pub fn rendered_output(output: &str) -> String {
+    output.lines()
+        .filter(|line| line.starts_with('{'))
+        .filter_map(|line| json::from_str::<Diagnostic>(line).ok())
+        .filter_map(|diagnostic| diagnostic.rendered)
+        .collect()
+}
+
+/// Extracts the number of errors from rustc's "aborting due to N previous errors" message.
+///
+/// Returns `None` if there is no such message (eg. because compilation succeeded).
+pub fn reported_error_count(output: &str) -> Option<usize> {
+    const PREFIX: &'static str = "aborting due to ";
+
+    output.lines()
+        .filter(|line| line.starts_with('{'))
+        .filter_map(|line| json::from_str::<Diagnostic>(line).ok())
+        .filter(|diagnostic| diagnostic.message.starts_with(PREFIX))
+        .filter_map(|diagnostic| {
+            let count = &diagnostic.message[PREFIX.len()..];
+            if count.starts_with("previous error") {
+                Some(1)
+            } else {
+                count.split_whitespace().next().and_then(|n| n.parse().ok())
+            }
+        })
+        .next()
+}
+
+fn parse_line(files: &[PathBuf], line: &str) -> Result<Vec<Message>, Box<Error>> {
     // The compiler sometimes intermingles non-JSON stuff into the
     // output.  This hack just skips over such lines. Yuck.
     if line.starts_with('{') {
         let diagnostic = json::from_str::<Diagnostic>(line)?;
         let mut expected_errors = vec![];
-        push_expected_errors(&mut expected_errors, &diagnostic, &[], file_name);
+        push_expected_errors(&mut expected_errors, &diagnostic, &[], files);
         Ok(expected_errors)
     } else {
         Ok(vec![])
@@ -115,10 +172,10 @@ fn parse_line(file_name: &str, line: &str) -> Result<Vec<Message>, Box<Error>> {
 fn push_expected_errors(expected_errors: &mut Vec<Message>,
                         diagnostic: &Diagnostic,
                         default_spans: &[&DiagnosticSpan],
-                        file_name: &str) {
+                        files: &[PathBuf]) {
     let spans_in_this_file: Vec<_> = diagnostic.spans
         .iter()
-        .filter(|span| Path::new(&span.file_name) == Path::new(&file_name))
+        .filter(|span| is_test_file(files, &span.file_name))
         .collect();
 
     let primary_spans: Vec<_> = spans_in_this_file.iter()
@@ -145,20 +202,24 @@ fn push_expected_errors(expected_errors: &mut Vec<Message>,
             // We should perhaps `.unwrap()` the parse
             let kind = MessageKind::from_str(&diagnostic.level).ok();
             expected_errors.push(Message {
+                file: PathBuf::from(&span.file_name),
                 line_num: span.line_start,
                 code: code.clone(),
                 kind,
                 msg: first_line.to_string(),
+                rendered: diagnostic.rendered.clone(),
             });
         }
     }
     for next_line in message_lines {
         for span in primary_spans {
             expected_errors.push(Message {
+                file: PathBuf::from(&span.file_name),
                 line_num: span.line_start,
                 code: code.clone(),
                 kind: None,
                 msg: next_line.to_string(),
+                rendered: None,
             });
         }
     }
@@ -168,10 +229,12 @@ fn push_expected_errors(expected_errors: &mut Vec<Message>,
         if let Some(ref suggested_replacement) = span.suggested_replacement {
             for (index, line) in suggested_replacement.lines().enumerate() {
                 expected_errors.push(Message {
+                    file: PathBuf::from(&span.file_name),
                     line_num: span.line_start + index,
                     kind: Some(MessageKind::Suggestion),
                     msg: line.to_string(),
                     code: code.clone(),
+                    rendered: None,
                 });
             }
         }
@@ -180,7 +243,7 @@ fn push_expected_errors(expected_errors: &mut Vec<Message>,
     // Add notes for the backtrace
     for span in primary_spans {
         for frame in &span.expansion {
-            push_backtrace(expected_errors, frame, file_name);
+            push_backtrace(expected_errors, frame, files);
         }
     }
 
@@ -188,32 +251,40 @@ fn push_expected_errors(expected_errors: &mut Vec<Message>,
     for span in spans_in_this_file.iter()
         .filter(|span| span.label.is_some()) {
         expected_errors.push(Message {
+            file: PathBuf::from(&span.file_name),
             line_num: span.line_start,
             kind: Some(MessageKind::Note),
             msg: span.label.clone().unwrap(),
             code: code.clone(),
+            rendered: None,
         });
     }
 
     // Flatten out the children.
     for child in &diagnostic.children {
-        push_expected_errors(expected_errors, child, primary_spans, file_name);
+        push_expected_errors(expected_errors, child, primary_spans, files);
     }
 }
 
 fn push_backtrace(expected_errors: &mut Vec<Message>,
                   expansion: &DiagnosticSpanMacroExpansion,
-                  file_name: &str) {
-    if Path::new(&expansion.span.file_name) == Path::new(&file_name) {
+                  files: &[PathBuf]) {
+    if is_test_file(files, &expansion.span.file_name) {
         expected_errors.push(Message {
+            file: PathBuf::from(&expansion.span.file_name),
             line_num: expansion.span.line_start,
             kind: Some(MessageKind::Note),
             msg: format!("in this expansion of {}", expansion.macro_decl_name),
             code: None,
+            rendered: None,
         });
     }
 
     for previous_expansion in &expansion.span.expansion {
-        push_backtrace(expected_errors, previous_expansion, file_name);
+        push_backtrace(expected_errors, previous_expansion, files);
     }
 }
+
+fn is_test_file(files: &[PathBuf], file_name: &str) -> bool {
+    files.iter().any(|file| file == Path::new(file_name))
+}
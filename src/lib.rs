@@ -15,36 +15,101 @@
 //! #[macro_use] extern crate compile_fail;
 //!
 //! run_compile_fail_tests!(Config {
-//!     cfail_path: PathBuf::from("tests/custom-compile-fail"),
+//!     cfail_paths: vec![PathBuf::from("tests/custom-compile-fail")],
 //!     wrapper_test: file!(),
 //!     ..Config::default()
 //! });
 //! ```
+//!
+//! # Per-directory settings
+//!
+//! Each test directory can contain a `compile-fail.toml` file with defaults for all tests in it:
+//!
+//! ```toml
+//! # Compile the tests with the 2018 edition instead of the one Cargo would use.
+//! edition = "2018"
+//! # Additional flags to pass to rustc.
+//! flags = ["-Dunsafe-code"]
+//! # Ignore warnings that aren't matched by a pattern instead of failing the test (default: true).
+//! strict-warnings = false
+//! # Require the first error to be matched by the first pattern (default: false).
+//! strict-primary-error = true
+//! # Regex replacements applied to compiler output (see `Config::normalize`).
+//! normalize = [["size: \\d+ bytes", "size: N bytes"]]
+//! # What is expected of the tests: "compile-fail" (the default), "build-fail", "check-pass",
+//! # "run-pass" or "run-fail".
+//! mode = "compile-fail"
+//! ```
+//!
+//! # Directives
+//!
+//! Comments at the top of a test, before any code, can contain directives that change how the
+//! test is run. They take precedence over the settings in `compile-fail.toml`:
+//!
+//! * `// build-fail`: Always fully build the test, even if `Config::check_only` is set. Needed for
+//!   errors that are only emitted during monomorphization or linking.
+//! * `// check-pass`: The test must type-check without any errors (or unexpected warnings). Useful
+//!   to show that the accepted counterpart of a compile-fail test really compiles.
+//! * `// run-pass`: The test must build without errors, and running it must succeed.
+//! * `// run-fail`: The test must build without errors, and running it must fail. This is meant
+//!   for safe abstractions relying on runtime checks. It can be combined with these directives:
+//!   * `// exit-code: 101`: The exit code the test must exit with (101 for a panic).
+//!   * `// panic-message: already borrowed`: Text the test must print to stderr.
+//! * `// timeout: 60s`: Kill the compiler and fail the test if compilation takes longer than this.
+//!   Overrides `Config::timeout`. Supported units are `ms`, `s` and `m`.
+//! * `// strict-primary-error`: The first error emitted by rustc must be matched by the first
+//!   pattern of the test (see `Config::strict_primary_error`).
+//! * `// normalize-stderr: "regex" -> "replacement"`: Adds a replacement to `Config::normalize`
+//!   for this test. Can be given multiple times.
+//! * `// ignore: reason`: Don't run the test unless `--ignored` or `--include-ignored` is passed.
+//! * `// known-bug: #123`: The test documents a bug and is expected not to match its patterns yet.
+//!   It is reported as a known bug instead of a failure, and fails once it starts passing, so that
+//!   the directive gets removed when the bug is fixed.
 
 #[macro_use] extern crate log;
 #[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
 extern crate serde;
-extern crate serde_json;
 extern crate env_logger;
 extern crate tempdir;
 extern crate termcolor;
 extern crate build_plan;
+extern crate glob;
+extern crate toml;
+extern crate num_cpus;
+extern crate wait_timeout;
+extern crate diff;
+extern crate regex;
+#[cfg(unix)] extern crate libc;
 
+mod annotate;
+mod args;
+mod batch;
 mod compile;
 mod config;
+mod discover;
+mod error;
 mod json;
+mod normalize;
 mod parse;
+mod process;
+mod report;
 mod runner;
+mod snapshot;
 mod status;
+mod variants;
 
 pub use config::Config;
+pub use error::{Error, Mismatch, TestFailure};
+pub use parse::AnnotationError;
+use args::TestArgs;
 use compile::Blueprint;
+use discover::{Test, find_tests};
 use parse::TestExpectation;
+use status::{Format, TestStatus};
 
 use tempdir::TempDir;
-use std::error::Error;
-use std::fs;
-use std::io::Read;
+use std::io::{self, Write};
 use std::path::PathBuf;
 
 /// Generates a `#[test]` function that manages all compile-fail tests.
@@ -66,81 +131,108 @@ macro_rules! run_compile_fail_tests {
     };
 }
 
-/// Locates compile-fail tests in the configured directory (`tests/compile-fail/*` by default).
-fn find_tests(config: &Config) -> Result<Vec<PathBuf>, Box<Error>> {
-    info!("searching for compile-fail tests, config = {:?}", config);
-
-    let mut tests = Vec::new();
-
-    for entry in fs::read_dir(&config.cfail_path)
-        .map_err(|e| format!("couldn't open {}: {}", config.cfail_path.display(), e))? {
-
-        let entry = entry?;
-
-        if entry.path().extension() != Some(std::ffi::OsStr::new("rs")) {
-            // Only consider `.rs` files. In reality, this is needed because of the `.gitkeep` in
-            // `tests/empty`.
-            break;
+/// Generates a `main` function that runs every compile-fail test as a separate test.
+///
+/// This is meant for integration tests that don't use the default test harness:
+///
+/// ```toml
+/// [[test]]
+/// name = "compile-fail"
+/// harness = false
+/// ```
+///
+/// Like `run_compile_fail_tests!`, this can be called with a `Config` structure.
+#[macro_export]
+macro_rules! compile_fail_harness {
+    () => {
+        compile_fail_harness!($crate::Config {
+            wrapper_test: file!(),
+            ..$crate::Config::default()
+        });
+    };
+    ( $e:expr ) => {
+        fn main() {
+            $crate::run_harness($e);
         }
+    };
+}
 
-        let ftype = entry.file_type()?;
-        if !ftype.is_file() {
-            return Err(format!(
-                "unsupported file type of compile-fail test '{}': {:?}",
-                entry.path().display(), ftype
-            ).into());
+/// Parses and runs `tests`.
+///
+/// Tests that can't be parsed (eg. because of a malformed annotation) fail without stopping the
+/// other tests. `args` decides whether ignored tests are run. If `cache_blueprint` is set, the
+/// `Blueprint` is reused across runs of the same test binary.
+fn parse_and_run<I>(config: &Config, i: I, args: &TestArgs, mut num_filtered_out: usize, cache_blueprint: bool) -> Result<(), Error>
+where I: IntoIterator<Item=Test> {
+    let mut tests = Vec::new();
+    let mut invalid = Vec::new();
+    for mut test in i {
+        let parsed = TestExpectation::parse(&test.path).and_then(|exp| {
+            test.settings.apply_config(config);
+            test.settings.apply(&exp.directives);
+            exp.check_mode(&test.path, test.settings.mode)?;
+            Ok(exp)
+        });
+        match parsed {
+            Ok(exp) => tests.push((test, exp)),
+            Err(e) => {
+                let error = error::from_parse_error(&test.path, e);
+                invalid.push((test, error));
+            }
         }
-
-        let mut s = String::new();
-        fs::File::open(entry.path())?.read_to_string(&mut s)?;
-
-        info!("found compile-fail test at {}", entry.path().display());
-        tests.push(entry.path().to_owned());
     }
 
-    // As a safeguard, raise an error when no test was found. This often indicates that a wrong
-    // directory was specified.
-    if tests.is_empty() {
-        return Err(format!("no compile-fail test found in {}", config.cfail_path.display()).into());
+    if args.ignored {
+        // Like libtest, count all tests that aren't ignored as filtered out
+        let num_tests = tests.len();
+        tests.retain(|&(_, ref exp)| exp.directives.ignore.is_some());
+        num_filtered_out += num_tests - tests.len();
+    }
+    if args.runs_ignored() {
+        for &mut (_, ref mut exp) in &mut tests {
+            exp.directives.ignore = None;
+        }
     }
 
-    Ok(tests)
-}
-
-fn parse_and_run<I>(config: &Config, i: I) -> Result<(), Box<Error>>
-where I: IntoIterator<Item=PathBuf> {
-    let tests = i.into_iter()
-        .map(|path| TestExpectation::parse(&path).map(|exp| (path, exp)))
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let mut blueprint = Blueprint::obtain(config)?;
+    let mut blueprint = if cache_blueprint {
+        Blueprint::obtain_cached(config)?
+    } else {
+        Blueprint::obtain(config)?
+    };
+    blueprint.set_check_only(config.check_only);
 
     let tempdir = TempDir::new("rust-compile-fail")?;
     info!("temporary output directory at {}", tempdir.path().display());
-    blueprint.set_out_dir(tempdir.path().to_owned());
-    runner::run(config, &blueprint, &tests)?;
-
-    Ok(())
+    let status = TestStatus::new(config, args.format, tests.len() + invalid.len(), num_filtered_out);
+    runner::run(config, &blueprint, tempdir.path(), tests, invalid, status)
 }
 
 /// This should **never** be visible to users of this crate. It is only used for tests.
 #[doc(hidden)]
-pub fn run_single_test(config: Config, path: PathBuf) -> Result<(), Box<Error>> {
+pub fn run_single_test(config: Config, path: PathBuf) -> Result<(), Error> {
     use std::iter;
 
     let _ = env_logger::init();
 
-    parse_and_run(&config, iter::once(path))
+    let test = Test::from_path(path)?;
+    parse_and_run(&config, iter::once(test), &TestArgs::default(), 0, false)
 }
 
 /// Runs all compile-fail tests and returns the test result as a `Result` instead of panicking on
 /// errors.
 ///
-/// Apart from that, works the same way `run_tests` does.
-pub fn try_run_tests(config: Config) -> Result<(), Box<Error>> {
+/// Apart from that, works the same way `run_tests` does. The `Error` tells failing tests
+/// (`Error::TestsFailed`, which lists the mismatches of every test) apart from problems that
+/// prevented the tests from running, like a failure to obtain the compiler invocation from Cargo.
+pub fn try_run_tests(config: Config) -> Result<(), Error> {
     let _ = env_logger::init();
 
-    parse_and_run(&config, find_tests(&config)?)
+    let args = TestArgs::from_filter_var();
+    let (selected, filtered_out): (Vec<_>, Vec<_>) = find_tests(&config)?
+        .into_iter()
+        .partition(|test| args.is_selected(&test.name));
+
+    parse_and_run(&config, selected, &args, filtered_out.len(), false)
 }
 
 /// Runs all compile-fail tests. Panics when a test fails.
@@ -148,13 +240,189 @@ pub fn try_run_tests(config: Config) -> Result<(), Box<Error>> {
 /// This function **must** be called from a test function named `compile_fail` contained in an
 /// integration test. The `run_tests!` macro will autogenerate such a function.
 ///
+/// Tests can be selected with the same arguments libtest uses (filters, `--exact` and `--skip`) in
+/// the `COMPILE_FAIL_FILTER` environment variable:
+///
+/// ```text
+/// $ COMPILE_FAIL_FILTER="borrowck/ --skip slow" cargo test --test compile-fail
+/// ```
+///
+/// The command line of the test binary is left to libtest, since its filters select the `#[test]`
+/// functions to run. Use `run_harness` to select compile-fail tests on the command line.
+///
 /// If any compile-fail test fails (or a different error was encountered), this will panic.
 pub fn run_tests(config: Config) {
+    let no_console_output = config.no_console_output;
+
     // Attempt to build the (currently running) compile_fail test
     match try_run_tests(config) {
         Ok(()) => {}
         Err(e) => {
-            panic!("{}", e);
+            panic!("{}", error_message(&e, no_console_output));
+        }
+    }
+}
+
+/// Runs compile-fail tests as the main function of a test binary without the default harness.
+///
+/// This understands the same command line arguments as libtest (`--list`, `--ignored`, filters,
+/// `--format json`, etc.), so each compile-fail test shows up as a separate test in IDEs and other
+/// tools. The `compile_fail_harness!` macro will autogenerate a `main` function calling this.
+///
+/// This also makes the test binary compatible with `cargo nextest`, which lists tests with
+/// `--list --format terse` and then runs every test in its own process with `--exact <name>`. To
+/// make that fast, the compiler invocation obtained from Cargo is cached next to the test binary.
+///
+/// Exits the process with status 101 if any test fails, like libtest does.
+pub fn run_harness(mut config: Config) {
+    let _ = env_logger::init();
+
+    let args = TestArgs::from_command_line();
+    if let Some(threads) = args.test_threads {
+        config.jobs = Some(threads);
+    }
+
+    if let Err(e) = run_harness_with_args(&config, &args) {
+        let _ = writeln!(io::stderr(), "{}", error_message(&e, config.no_console_output));
+        std::process::exit(101);
+    }
+}
+
+/// Returns the message describing `e` after running the tests.
+///
+/// Test failures that were already printed to the console are only summarized.
+fn error_message(e: &Error, no_console_output: bool) -> String {
+    match *e {
+        Error::TestsFailed(ref failures) if !no_console_output => {
+            format!("{} compile-fail tests failed", failures.len())
+        }
+        ref e => e.to_string(),
+    }
+}
+
+fn run_harness_with_args(config: &Config, args: &TestArgs) -> Result<(), Error> {
+    let (mut selected, filtered_out): (Vec<_>, Vec<_>) = find_tests(config)?
+        .into_iter()
+        .partition(|test| args.is_selected(&test.name));
+
+    if args.list {
+        if args.ignored {
+            selected.retain(is_ignored);
         }
+        return list_tests(&selected, args.format);
     }
+
+    // Tools like `cargo nextest` run the binary once per test, so the blueprint is cached
+    parse_and_run(config, selected, args, filtered_out.len(), true)
+}
+
+/// Compiles all compile-fail tests and rewrites their `//~` annotations to match the errors rustc
+/// reports.
+///
+/// Existing annotations are replaced, and annotations are added for errors that had none. Error
+/// codes are preferred over messages. Code and other comments are left untouched, except for
+/// `// fixed:` comments of lines that no longer have an error. If `dry_run` is set, the changes are
+/// only printed as a diff.
+///
+/// This is meant to be called from an ignored test, so that it can be run on demand:
+///
+/// ```no_run
+/// extern crate compile_fail;
+///
+/// use compile_fail::Config;
+///
+/// #[test]
+/// #[ignore]
+/// fn bless_annotations() {
+///     compile_fail::bless_annotations(Config {
+///         wrapper_test: file!(),
+///         ..Config::default()
+///     }, false).unwrap();
+/// }
+/// ```
+///
+/// Review the result before committing it: The new annotations describe what rustc does, not what
+/// it should do.
+pub fn bless_annotations(config: Config, dry_run: bool) -> Result<(), Error> {
+    let _ = env_logger::init();
+
+    let tests = find_tests(&config)?;
+    let blueprint = Blueprint::obtain(&config)?;
+    annotate::bless(&config, &blueprint, tests, dry_run)
+}
+
+/// Compiles all compile-fail tests and turns their message patterns into error code patterns.
+///
+/// Error codes don't change between compiler versions, while messages do. A pattern is converted
+/// if it matches exactly one error, and no other error on the same line has the same code.
+/// Patterns that can't be converted without ambiguity are reported instead. If `dry_run` is set,
+/// the changes are only printed as a diff.
+///
+/// Like `bless_annotations`, this is meant to be called from an ignored test.
+pub fn upgrade_patterns(config: Config, dry_run: bool) -> Result<(), Error> {
+    let _ = env_logger::init();
+
+    let tests = find_tests(&config)?;
+    let blueprint = Blueprint::obtain(&config)?;
+    annotate::upgrade(&config, &blueprint, tests, dry_run)
+}
+
+/// Compiles the new test at `path` and returns it with suggested `//~` annotations.
+///
+/// This is meant for writing new tests: Write the code, let this function suggest the patterns for
+/// the errors rustc reports, and review them. Error codes are preferred over messages. Warnings
+/// can't be matched by patterns, so a note is appended for each warning that would fail the test.
+/// If the test compiles without errors, a warning is appended, since it wouldn't test anything.
+///
+/// `path` can be a single `.rs` file or a directory containing a `main.rs` or `lib.rs`. It is
+/// compiled like every other test, using `config` and the `compile-fail.toml` next to it. The file
+/// itself isn't changed.
+///
+/// ```no_run
+/// extern crate compile_fail;
+///
+/// use compile_fail::Config;
+/// use std::path::PathBuf;
+///
+/// #[test]
+/// #[ignore]
+/// fn scaffold() {
+///     print!("{}", compile_fail::scaffold(Config {
+///         wrapper_test: file!(),
+///         ..Config::default()
+///     }, PathBuf::from("tests/compile-fail/new-test.rs")).unwrap());
+/// }
+/// ```
+pub fn scaffold(config: Config, path: PathBuf) -> Result<String, Error> {
+    let _ = env_logger::init();
+
+    let test = Test::from_path(path)?;
+    let blueprint = Blueprint::obtain(&config)?;
+    annotate::scaffold(&config, &blueprint, test)
+}
+
+/// Determines whether `test` has an `// ignore` directive. Tests that can't be parsed aren't.
+fn is_ignored(test: &Test) -> bool {
+    TestExpectation::parse(&test.path)
+        .map(|exp| exp.directives.ignore.is_some())
+        .unwrap_or(false)
+}
+
+/// Prints the names of `tests` in the format libtest uses for `--list`.
+fn list_tests(tests: &[Test], format: Format) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for test in tests {
+        match format {
+            Format::Json => writeln!(out, "{}", json!({ "type": "test", "event": "discovered", "name": test.name }))?,
+            Format::Pretty | Format::Terse => writeln!(out, "{}: test", test.name)?,
+        }
+    }
+
+    if format == Format::Pretty {
+        writeln!(out)?;
+        writeln!(out, "{} test{}, 0 benchmarks", tests.len(), if tests.len() == 1 { "" } else { "s" })?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,128 @@
+//! Removes machine-specific details from compiler output.
+//!
+//! Rendered diagnostics and messages can contain absolute paths, hashes Cargo appends to crate
+//! file names, and paths into the Rust sources, all of which depend on the machine and toolchain.
+//! Normalization replaces them with placeholders before patterns are matched and snapshots are
+//! compared:
+//!
+//! * The directory containing the test becomes `$DIR`.
+//! * Crate hashes (eg. the `-1a2b3c4d5e6f7a8b` in `libeither-1a2b3c4d5e6f7a8b.rlib`) are removed.
+//! * Paths into the sources of the standard library become `$SRC_DIR`.
+//!
+//! Afterwards, the user-defined replacements from `Config::normalize`, `compile-fail.toml` and
+//! `// normalize-stderr` directives are applied in that order.
+
+use config::TestSettings;
+
+use regex::{self, Regex};
+
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+/// A list of regex replacements applied to compiler output.
+pub struct Normalizer {
+    /// Replacements for `$DIR`, `$SRC_DIR` and crate hashes. A literal `$` is written as `$$`.
+    builtin: Vec<(Regex, &'static str)>,
+    /// User-defined replacements, which may refer to capture groups (eg. `$1`).
+    custom: Vec<(Regex, String)>,
+}
+
+impl Normalizer {
+    /// Creates the normalizer for the test at `path`.
+    ///
+    /// `sysroot` is the sysroot of the compiler, if known.
+    pub fn new(path: &Path, settings: &TestSettings, sysroot: Option<&Path>) -> Result<Self, Box<Error>> {
+        let mut builtin = Vec::new();
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        // rustc prints paths the way they were passed to it, but also has absolute ones in some
+        // messages. Replace the longer absolute path first.
+        if let Ok(cwd) = env::current_dir() {
+            builtin.push((directory(&cwd.join(dir))?, "$$DIR$end"));
+        }
+        if dir != Path::new("") {
+            builtin.push((directory(dir)?, "$$DIR$end"));
+        }
+
+        if let Some(sysroot) = sysroot {
+            builtin.push((directory(&sysroot.join("lib/rustlib/src/rust"))?, "$$SRC_DIR$end"));
+        }
+        // The standard library is usually built with its source paths remapped to this
+        builtin.push((Regex::new(r"/rustc/[0-9a-f]{40}")?, "$$SRC_DIR"));
+        // Only hashes following a crate name are removed, so that eg. negative numbers stay intact
+        builtin.push((Regex::new(r"\b(?P<name>[A-Za-z_]\w*)-[0-9a-f]{16}\b")?, "$name"));
+
+        let custom = settings.normalize.iter()
+            .map(|&(ref pattern, ref replacement)| {
+                Regex::new(pattern)
+                    .map(|regex| (regex, replacement.clone()))
+                    .map_err(|e| format!("invalid normalization regex '{}': {}", pattern, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Normalizer { builtin, custom })
+    }
+
+    /// Applies all replacements to `text`.
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for &(ref regex, replacement) in &self.builtin {
+            text = regex.replace_all(&text, replacement).into_owned();
+        }
+        for &(ref regex, ref replacement) in &self.custom {
+            text = regex.replace_all(&text, &**replacement).into_owned();
+        }
+        text
+    }
+}
+
+/// Creates a regex matching the directory `path`, but not other files or directories whose name
+/// starts with the same characters.
+///
+/// The path must be followed by a path separator or a character that can't be part of a name, which
+/// is captured as `end` since it belongs to the match.
+fn directory(path: &Path) -> Result<Regex, Box<Error>> {
+    let path = path.to_str().ok_or_else(|| format!("path {} isn't valid UTF-8", path.display()))?;
+    Ok(Regex::new(&format!(r"{}(?P<end>[^\w.-]|$)", regex::escape(path)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_output() {
+        let settings = TestSettings {
+            normalize: vec![(r"size: \d+".to_string(), "size: N".to_string())],
+            ..TestSettings::default()
+        };
+        let normalizer = Normalizer::new(Path::new("tests/compile-fail/test.rs"), &settings, Some(Path::new("/sysroot"))).unwrap();
+        assert_eq!(
+            normalizer.apply(" --> tests/compile-fail/test.rs:1:1"),
+            " --> $DIR/test.rs:1:1"
+        );
+        assert_eq!(
+            normalizer.apply("found crate `either` in target/debug/deps/libeither-1a2b3c4d5e6f7a8b.rlib"),
+            "found crate `either` in target/debug/deps/libeither.rlib"
+        );
+        assert_eq!(
+            normalizer.apply("/sysroot/lib/rustlib/src/rust/src/libcore/cell.rs"),
+            "$SRC_DIR/src/libcore/cell.rs"
+        );
+        assert_eq!(
+            normalizer.apply("/rustc/59807616e1fa2540724bfbac14d7976d7e4a3860/library/core/src/cell.rs"),
+            "$SRC_DIR/library/core/src/cell.rs"
+        );
+        assert_eq!(normalizer.apply("type size: 64 bits"), "type size: N bits");
+
+        assert_eq!(
+            normalizer.apply("couldn't read tests/compile-fail: not found; see tests/compile-fail-extra/test.rs"),
+            "couldn't read $DIR: not found; see tests/compile-fail-extra/test.rs"
+        );
+        assert_eq!(
+            normalizer.apply("literal out of range: `-1234567890123456`, found libeither-1a2b3c4d5e6f7a8b.rmeta"),
+            "literal out of range: `-1234567890123456`, found libeither.rmeta"
+        );
+    }
+}
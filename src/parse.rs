@@ -1,14 +1,18 @@
 //! Parses compile-fail tests to extract expected errors.
 
-// Note: This does not support any kind of header directive that compiletest-rs supports
+// Note: This supports only a few of the header directives compiletest-rs supports, and uses its
+// own syntax for them.
 
+use config::Mode;
 use json::Message;
 
 use std::error::Error;
-use std::path::Path;
-use std::fs::File;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
 use std::io::Read;
 use std::str::FromStr;
+use std::time::Duration;
 
 /// The different messages rustc can emit.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -56,112 +60,523 @@ pub enum Matcher {
 /// A pattern that can match a compiler message.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Pattern {
+    /// The file containing the pattern. Messages must point into this file.
+    pub file: PathBuf,
     /// The kind of message we expect.
     pub kind: Option<MessageKind>,
     /// Describes which messages this pattern matches.
     pub matcher: Matcher,
     /// The line at which the message must point.
     pub line_num: usize,
+    /// The line containing the `//~` annotation this pattern was parsed from.
+    pub annotation_line: usize,
 }
 
 impl Pattern {
+    /// Creates an error pattern for line `line_num` of `test.rs`, annotated on the same line, for
+    /// use in tests.
+    #[cfg(test)]
+    pub fn error(line_num: usize, matcher: Matcher) -> Self {
+        Pattern {
+            file: PathBuf::from("test.rs"),
+            kind: Some(MessageKind::Error),
+            matcher,
+            line_num,
+            annotation_line: line_num,
+        }
+    }
+
     /// Determines whether this `Pattern` matches a `Message` from the compiler.
     pub fn matches(&self, msg: &Message) -> bool {
-        if self.kind != msg.kind {
-            // kind must match *exactly*
+        if self.line_num != msg.line_num || self.file != msg.file {
+            // file and line must match *exactly*
             return false;
         }
 
-        if self.line_num != msg.line_num {
-            // line must match *exactly*
+        if !self.matches_text(msg) {
+            return false;
+        }
+
+        info!("matches: pattern {:?} matches message {:?}", self, msg);
+        true
+    }
+
+    /// Determines whether this `Pattern` matches the kind and message (or code) of a `Message`,
+    /// regardless of where it points.
+    pub fn matches_text(&self, msg: &Message) -> bool {
+        if self.kind != msg.kind {
+            // kind must match *exactly*
             return false;
         }
 
         // The pattern must be a substring of the message. For this reason, patterns may not be the
         // empty string (they would match everything).
         match self.matcher {
-            Matcher::Code(ref code) if msg.code.as_ref() != Some(code) => {
-                return false;
-            }
-            Matcher::Msg(ref message) if !msg.msg.contains(message) => {
-                return false;
-            }
-            _ => {}
+            Matcher::Code(ref code) => msg.code.as_ref() == Some(code),
+            Matcher::Msg(ref message) => msg.msg.contains(message),
         }
-
-        info!("matches: pattern {:?} matches message {:?}", self, msg);
-        true
     }
 }
 
 /// Expected compiler messages/errors parsed from a test.
 #[derive(Debug)]
 pub struct TestExpectation {
+    /// The file that is passed to rustc.
+    ///
+    /// For single-file tests, this is the test itself. For directory tests, this is the `main.rs`
+    /// or `lib.rs` inside the directory.
+    pub entry_point: PathBuf,
+    /// All source files belonging to the test. Messages pointing into any of them are considered.
+    pub files: Vec<PathBuf>,
     pub expected_msgs: Vec<Pattern>,
+    /// Alternatives for annotated lines, given by `// fixed:` comments.
+    pub fixes: Vec<Fix>,
+    /// Directives in the header of the entry point.
+    pub directives: Directives,
+}
+
+/// Code that should make the errors on a line go away when it replaces the line.
+///
+/// Given by a `// fixed: <code>` comment, which applies to the closest line above it that isn't
+/// blank or a comment. Used by `Config::verify_fixes`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fix {
+    /// The file containing the line.
+    pub file: PathBuf,
+    /// The line to replace.
+    pub line_num: usize,
+    /// The code to replace the line with.
+    pub code: String,
+}
+
+/// A malformed `//~` annotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationError {
+    /// The file containing the annotation.
+    pub file: PathBuf,
+    /// The line containing the annotation.
+    pub line: usize,
+    /// The column (in characters, starting at 1) at which the problem was found.
+    pub column: usize,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for AnnotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file.display(), self.line, self.column, self.message)
+    }
+}
+
+impl Error for AnnotationError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// All malformed annotations found in a test, in the order they appear in.
+#[derive(Debug)]
+pub struct AnnotationErrors(pub Vec<AnnotationError>);
+
+impl fmt::Display for AnnotationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "invalid annotation at {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AnnotationErrors {
+    fn description(&self) -> &str {
+        "invalid annotations"
+    }
 }
 
 impl TestExpectation {
-    /// Read the file at `path` and parse all expected errors.
+    /// Read the test at `path` and parse all expected errors.
+    ///
+    /// `path` can either be a single `.rs` file or a directory containing a `main.rs` or `lib.rs`,
+    /// in which case patterns are collected from all `.rs` files in the directory.
     pub fn parse(path: &Path) -> Result<Self, Box<Error>> {
-        let mut file = File::open(path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        drop(file);
+        let (entry_point, files) = if path.is_dir() {
+            let entry_point = entry_point(path).ok_or_else(|| format!(
+                "directory test {} contains neither a `main.rs` nor a `lib.rs`", path.display()
+            ))?;
+            let mut files = Vec::new();
+            collect_source_files(path, &mut files)?;
+            (entry_point, files)
+        } else {
+            (path.to_path_buf(), vec![path.to_path_buf()])
+        };
 
-        let patterns = Parser::new().parse(&content)?;
+        // Annotation errors in all files are reported together
+        let mut contents = Vec::new();
+        let mut patterns = Vec::new();
+        let mut annotation_errors = Vec::new();
+        for file in &files {
+            let mut content = String::new();
+            File::open(file)?.read_to_string(&mut content)?;
+            match Parser::new(file).parse(&content) {
+                Ok(file_patterns) => patterns.extend(file_patterns),
+                Err(errors) => annotation_errors.extend(errors),
+            }
+            contents.push(content);
+        }
+        if !annotation_errors.is_empty() {
+            return Err(Box::new(AnnotationErrors(annotation_errors)));
+        }
 
-        if patterns.is_empty() {
-            return Err(format!("no error patterns found in {}", path.display()).into());
+        let mut fixes = Vec::new();
+        let mut directives = Directives::default();
+        for (file, content) in files.iter().zip(&contents) {
+
+            for fix in parse_fixes(file, content).map_err(|e| format!("{}: {}", file.display(), e))? {
+                if !patterns.iter().any(|pat: &Pattern| pat.file == fix.file && pat.line_num == fix.line_num) {
+                    return Err(format!(
+                        "{}: `// fixed:` refers to line {}, which has no error pattern",
+                        file.display(), fix.line_num
+                    ).into());
+                }
+                fixes.push(fix);
+            }
+
+            if *file == entry_point {
+                directives = parse_directives(content)
+                    .map_err(|e| format!("{}: {}", file.display(), e))?;
+            }
         }
 
         Ok(TestExpectation {
+            entry_point,
+            files,
             expected_msgs: patterns,
+            fixes,
+            directives,
         })
     }
+
+    /// Checks that the patterns are consistent with the test's `mode`.
+    ///
+    /// Tests that must fail to compile need at least one pattern, while tests that must compile
+    /// can't have any.
+    pub fn check_mode(&self, path: &Path, mode: Mode) -> Result<(), Box<Error>> {
+        if mode.expects_errors() && self.expected_msgs.is_empty() {
+            return Err(format!("no error patterns found in {}", path.display()).into());
+        }
+        if !mode.expects_errors() && !self.expected_msgs.is_empty() {
+            return Err(format!("{} is a {:?} test, but contains error patterns", path.display(), mode).into());
+        }
+        if mode != Mode::RunFail
+            && (self.directives.exit_code.is_some() || self.directives.panic_message.is_some()) {
+            return Err(format!("{}: `exit-code` and `panic-message` can only be used in run-fail tests", path.display()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Settings specified by directives in the header of a test.
+///
+/// The header consists of all comments and empty lines at the start of a test. Directives are
+/// comments of the form `// name` or `// name: value`, all other comments are ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct Directives {
+    /// Test mode selected by `// build-fail`, `// check-pass`, `// run-pass` or `// run-fail`.
+    pub mode: Option<Mode>,
+    /// Time limit for the compiler, set by `// timeout: 60s`.
+    pub timeout: Option<Duration>,
+    /// Reason for not running the test, set by `// ignore: reason`.
+    pub ignore: Option<String>,
+    /// Issue describing why the test doesn't pass yet, set by `// known-bug: #123`.
+    pub known_bug: Option<String>,
+    /// Exit code a run-fail test must exit with, set by `// exit-code: 101`.
+    pub exit_code: Option<i32>,
+    /// Text a run-fail test must print to stderr, set by `// panic-message: already borrowed`.
+    pub panic_message: Option<String>,
+    /// Set by `// strict-primary-error` (see `TestSettings::strict_primary_error`).
+    pub strict_primary_error: bool,
+    /// Replacements given by `// normalize-stderr: "regex" -> "replacement"`.
+    pub normalize: Vec<(String, String)>,
+}
+
+/// Parses the directives in the header of the test source `content`.
+pub fn parse_directives(content: &str) -> Result<Directives, Box<Error>> {
+    let mut directives = Directives::default();
+
+    for (lineno, line) in content.lines()
+        .enumerate()
+        .map(|(lineno, line)| (lineno + 1, line.trim())) {
+
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with("//") {
+            // The header ends at the first line of code
+            break;
+        }
+        if line.starts_with("///") || line.starts_with("//!") || line.starts_with("//~") {
+            // Doc comments and patterns are never directives
+            continue;
+        }
+
+        let comment = line[2..].trim();
+        let (name, value) = match comment.find(':') {
+            Some(colon) => (comment[..colon].trim(), Some(comment[colon+1..].trim())),
+            None => (comment, None),
+        };
+
+        match name {
+            "build-fail" | "check-pass" | "run-pass" | "run-fail" => {
+                if value.is_some() {
+                    return Err(format!("in line {}: the `{}` directive does not take a value", lineno, name).into());
+                }
+                if let Some(prev) = directives.mode {
+                    return Err(format!("in line {}: test mode was already set to {:?}", lineno, prev).into());
+                }
+                directives.mode = Some(match name {
+                    "build-fail" => Mode::BuildFail,
+                    "check-pass" => Mode::CheckPass,
+                    "run-pass" => Mode::RunPass,
+                    _ => Mode::RunFail,
+                });
+            }
+            "timeout" => {
+                let value = value.ok_or_else(|| format!("in line {}: the `timeout` directive needs a value", lineno))?;
+                let timeout = parse_duration(value)
+                    .map_err(|e| format!("in line {}: {}", lineno, e))?;
+                if let Some(prev) = directives.timeout {
+                    return Err(format!("in line {}: timeout was already set to {:?}", lineno, prev).into());
+                }
+                directives.timeout = Some(timeout);
+            }
+            "ignore" | "known-bug" | "panic-message" => {
+                let value = match value {
+                    Some(value) if !value.is_empty() => value.to_string(),
+                    _ => return Err(format!("in line {}: the `{}` directive needs a value", lineno, name).into()),
+                };
+                let directive = match name {
+                    "ignore" => &mut directives.ignore,
+                    "known-bug" => &mut directives.known_bug,
+                    _ => &mut directives.panic_message,
+                };
+                if let Some(ref prev) = *directive {
+                    return Err(format!("in line {}: `{}` was already set to {:?}", lineno, name, prev).into());
+                }
+                *directive = Some(value);
+            }
+            "strict-primary-error" => {
+                if value.is_some() {
+                    return Err(format!("in line {}: the `{}` directive does not take a value", lineno, name).into());
+                }
+                directives.strict_primary_error = true;
+            }
+            "normalize-stderr" => {
+                let value = value.ok_or_else(|| format!("in line {}: the `{}` directive needs a value", lineno, name))?;
+                let rule = parse_replacement(value)
+                    .map_err(|e| format!("in line {}: {}", lineno, e))?;
+                directives.normalize.push(rule);
+            }
+            "exit-code" => {
+                let value = value.ok_or_else(|| format!("in line {}: the `exit-code` directive needs a value", lineno))?;
+                let code = value.parse()
+                    .map_err(|_| format!("in line {}: invalid exit code '{}'", lineno, value))?;
+                if let Some(prev) = directives.exit_code {
+                    return Err(format!("in line {}: exit code was already set to {}", lineno, prev).into());
+                }
+                directives.exit_code = Some(code);
+            }
+            _ => {
+                // Not a directive, just a regular comment
+            }
+        }
+    }
+
+    Ok(directives)
+}
+
+/// Parses the `// fixed:` comments in `content`, the source of `file`.
+///
+/// Consecutive comments applying to the same line are joined, so that longer code can be split
+/// across multiple comments.
+fn parse_fixes(file: &Path, content: &str) -> Result<Vec<Fix>, Box<Error>> {
+    const START: &'static str = "// fixed:";
+
+    let mut fixes: Vec<Fix> = Vec::new();
+    let mut last_code_line = 0;
+    for (lineno, line) in content.lines()
+        .enumerate()
+        .map(|(lineno, line)| (lineno + 1, line.trim())) {
+
+        if line.starts_with(START) {
+            if last_code_line == 0 {
+                return Err(format!("in line {}: `// fixed:` must follow the line it fixes", lineno).into());
+            }
+
+            let code = line[START.len()..].trim();
+            if fixes.last().map_or(false, |fix| fix.line_num == last_code_line) {
+                let fix = fixes.last_mut().unwrap();
+                fix.code.push(' ');
+                fix.code.push_str(code);
+            } else {
+                fixes.push(Fix {
+                    file: file.to_path_buf(),
+                    line_num: last_code_line,
+                    code: code.to_string(),
+                });
+            }
+        } else if !line.is_empty() && !line.starts_with("//") {
+            last_code_line = lineno;
+        }
+    }
+
+    Ok(fixes)
+}
+
+/// Parses a replacement of the form `"regex" -> "replacement"`.
+fn parse_replacement(s: &str) -> Result<(String, String), Box<Error>> {
+    let (regex, rest) = parse_quoted(s.trim())?;
+    let rest = rest.trim_left();
+    if !rest.starts_with("->") {
+        return Err(format!("expected `\"regex\" -> \"replacement\"`, found '{}'", s).into());
+    }
+    let (replacement, rest) = parse_quoted(rest[2..].trim_left())?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected '{}' after replacement", rest.trim()).into());
+    }
+    Ok((regex, replacement))
+}
+
+/// Parses a string in double quotes at the start of `s`, returning it and the rest of `s`.
+///
+/// Only `\"` is treated as an escape, so that backslashes in regexes can be written as usual.
+fn parse_quoted(s: &str) -> Result<(String, &str), Box<Error>> {
+    if !s.starts_with('"') {
+        return Err(format!("expected a string in double quotes, found '{}'", s).into());
+    }
+
+    let mut string = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &s[i+1..])),
+            '\\' if s[i+1..].starts_with('"') => {
+                chars.next();
+                string.push('"');
+            }
+            c => string.push(c),
+        }
+    }
+    Err(format!("unterminated string '{}'", s).into())
 }
 
-struct Parser {
+/// Parses a duration like `60s`, `2m` or `500ms`. A number without unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, Box<Error>> {
+    let s = s.trim();
+    let unit_start = s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+    let (number, unit) = s.split_at(unit_start);
+    let number = number.parse::<u64>().map_err(|_| format!("invalid duration '{}'", s))?;
+
+    Ok(match unit.trim() {
+        "" | "s" => Duration::from_secs(number),
+        "ms" => Duration::from_millis(number),
+        "m" | "min" => Duration::from_secs(number.checked_mul(60)
+            .ok_or_else(|| format!("invalid duration '{}': too long", s))?),
+        unit => return Err(format!("invalid duration '{}': unknown unit '{}'", s, unit).into()),
+    })
+}
+
+/// Returns the crate root of a directory test (`main.rs` or `lib.rs`), if it has one.
+pub fn entry_point(dir: &Path) -> Option<PathBuf> {
+    ["main.rs", "lib.rs"].iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Recursively collects all `.rs` files in `dir` into `files`, in a stable order.
+fn collect_source_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<Error>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_source_files(&path, files)?;
+        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+struct Parser<'a> {
+    /// The file being parsed.
+    file: &'a Path,
     expected_msgs: Vec<Pattern>,
     /// Last line number that contained a parsed `Message`. 0 if none were parsed yet.
     last_line_with_pattern: usize,
 }
 
-impl Parser {
-    pub fn new() -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(file: &'a Path) -> Self {
         Self {
+            file,
             expected_msgs: Vec::new(),
             last_line_with_pattern: 0,
         }
     }
 
-    pub fn parse(mut self, content: &str) -> Result<Vec<Pattern>, Box<Error>> {
+    /// Parses all patterns in `content`, collecting the errors of all malformed annotations.
+    pub fn parse(mut self, content: &str) -> Result<Vec<Pattern>, Vec<AnnotationError>> {
+        let mut errors = Vec::new();
         for (lineno, line) in content.lines()
             .enumerate()
             .map(|(lineno, line)| (lineno + 1, line)) {
 
-            if let Some(pat) = self.parse_line(lineno, line)? {
-                self.last_line_with_pattern = lineno;
-                self.expected_msgs.push(pat);
+            match self.parse_line(lineno, line) {
+                Ok(Some(pat)) => {
+                    self.last_line_with_pattern = lineno;
+                    self.expected_msgs.push(pat);
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(e),
             }
         }
 
-        Ok(self.expected_msgs)
+        if errors.is_empty() {
+            Ok(self.expected_msgs)
+        } else {
+            Err(errors)
+        }
     }
 
     /// Parses a line which may contain a `Pattern`.
-    pub fn parse_line(&self, lineno: usize, line: &str) -> Result<Option<Pattern>, Box<Error>> {
+    pub fn parse_line(&self, lineno: usize, line: &str) -> Result<Option<Pattern>, AnnotationError> {
         const START: &'static str = "//~";
         if let Some(start) = line.find(START) {
             // line contains a `//~` pattern
-            let pat = &line[start+START.len()..];
-            let pattern = self.parse_pattern(pat, lineno)?;
+            let pattern = self.parse_pattern(line, start + START.len(), lineno)?;
             Ok(Some(pattern))
         } else {
             Ok(None)
         }
     }
 
-    fn parse_pattern(&self, mut pattern: &str, lineno: usize) -> Result<Pattern, Box<Error>> {
+    /// Parses the pattern starting at byte `start` of `line`.
+    fn parse_pattern(&self, line: &str, start: usize, lineno: usize) -> Result<Pattern, AnnotationError> {
+        // Reports an error at the start of `rest`, which is the unparsed end of `line`
+        let error = |rest: &str, message: String| AnnotationError {
+            file: self.file.to_path_buf(),
+            line: lineno,
+            column: line[..line.len() - rest.len()].chars().count() + 1,
+            message,
+        };
+        let mut pattern = &line[start..];
+
         // The beginning of the pattern determines the line it matches.
         // "|"         => same line as pattern on last line
         // "^" times N => N lines above the current one
@@ -170,30 +585,27 @@ impl Parser {
         let target_line = if chars.next() == Some('|') {
             // This form uses the same target line as the pattern in the line before (which is
             // required).
-            pattern = chars.as_str();
 
             // The last line must contain a pattern.
             if self.last_line_with_pattern != 0 && self.last_line_with_pattern == lineno - 1 {
+                pattern = chars.as_str();
                 self.expected_msgs.last().unwrap().line_num
             } else {
-                return Err(format!(
-                    "in line {}: a `//~|` pattern must be directly preceded by another pattern",
-                    lineno
-                ).into());
+                return Err(error(pattern, "a `//~|` pattern must be directly preceded by another pattern".to_string()));
             }
         } else {
             // reset iterator
             let offset = pattern.chars().take_while(|&c| c == '^').count();
-            pattern = &pattern[offset..];
-            debug!("offset: {} (current line={}), left = '{}'", offset, lineno, pattern);
+            debug!("offset: {} (current line={}), left = '{}'", offset, lineno, &pattern[offset..]);
 
             match lineno.checked_sub(offset) {
                 Some(n) if n > 0 => {
                     // valid line number
+                    pattern = &pattern[offset..];
                     n
                 }
                 _ => {
-                    return Err(format!("in line {}: invalid line offset before line 1", lineno).into());
+                    return Err(error(pattern, "invalid line offset before line 1".to_string()));
                 }
             }
         };
@@ -203,7 +615,7 @@ impl Parser {
         pattern = pattern.trim_left();
         let kind_str = pattern.chars().take_while(|c| c.is_alphabetic()).collect::<String>();
         let kind = kind_str.parse::<MessageKind>()
-            .map_err(|()| format!("'{}' is an invalid message kind", kind_str))?;
+            .map_err(|()| error(pattern, format!("'{}' is an invalid message kind", kind_str)))?;
         pattern = &pattern[kind_str.len()..];
         debug!("kind = {} = {:?}, left = '{}'", kind_str, kind, pattern);
 
@@ -213,30 +625,35 @@ impl Parser {
         let matcher = match chars.next() {
             Some(':') => {
                 let message = chars.as_str().trim_left();
-                pattern = &pattern[0..0];   // consumed
                 if message.is_empty() {
-                    return Err(format!("in line {}: error patterns may not be empty", lineno).into());
+                    return Err(error(pattern, "error patterns may not be empty".to_string()));
                 }
+                pattern = &pattern[pattern.len()..];   // consumed
 
                 Matcher::Msg(message.to_string())
             }
             Some('[') => {
                 let code = chars.take_while(|&c| c != ']').collect::<String>();
+                if !pattern[1 + code.len()..].starts_with(']') {
+                    return Err(error(pattern, "unterminated error code, expected `]`".to_string()));
+                }
                 pattern = &pattern[code.len()+2..];
                 Matcher::Code(code)
             }
-            _ => return Err(format!("expected `: <message>` or `[Exxxx]`").into()),
+            _ => return Err(error(pattern, "expected `: <message>` or `[Exxxx]`".to_string())),
         };
 
         // Make sure `pattern` is now empty
         if !pattern.is_empty() {
-            return Err(format!("unconsumed input in pattern: '{}'", pattern).into());
+            return Err(error(pattern, format!("unconsumed input in pattern: '{}'", pattern)));
         }
 
         Ok(Pattern {
+            file: self.file.to_path_buf(),
             matcher,
             kind: Some(kind),
             line_num: target_line,
+            annotation_line: lineno,
         })
     }
 }
@@ -246,17 +663,17 @@ mod tests {
     use super::*;
 
     fn pattern(lineno: usize, line: &str) -> Pattern {
-        let p = Parser::new();
+        let p = Parser::new(Path::new("test.rs"));
         p.parse_line(lineno, line).unwrap().unwrap()
     }
 
     fn patterns(text: &str) -> Vec<Pattern> {
-        let p = Parser::new();
+        let p = Parser::new(Path::new("test.rs"));
         p.parse(text).unwrap()
     }
 
     fn invalid_pattern(line: &str, err_msg: &str) {
-        let err = Parser::new().parse_line(1, line).unwrap_err().to_string();
+        let err = Parser::new(Path::new("test.rs")).parse_line(1, line).unwrap_err().to_string();
         assert!(err.contains(err_msg), "'{}' does not contain '{}'", err, err_msg);
     }
 
@@ -269,37 +686,174 @@ mod tests {
         invalid_pattern("//~ error:", "error patterns may not be empty");
         invalid_pattern("//~ error another: bla", "expected `:");
         invalid_pattern("//~ error[code]: but also message", "unconsumed input");
+        invalid_pattern("//~ error[E0308", "unterminated error code");
+    }
+
+    #[test]
+    fn collects_annotation_errors() {
+        let errors = Parser::new(Path::new("test.rs")).parse("\
+fn main() {
+    let () = 0;     //~ eror: mismatched types
+    let x = 1;      //~ error[E0001]
+    //~^^^^^ error: before the start
+    x = 2;  //~ error: ok
+}
+").unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(2, 25), (4, 8)]);
+        assert_eq!(errors[0].to_string(), "test.rs:2:25: 'eror' is an invalid message kind");
+    }
+
+    #[test]
+    fn parses_directives() {
+        assert_eq!(parse_directives("fn main() {}").unwrap(), Directives::default());
+        assert_eq!(parse_directives("\
+            //! Docs
+            // Some regular comment: this is not a directive
+
+            // build-fail
+            // timeout: 2m
+            // ignore: needs a newer compiler
+            // known-bug: #123
+            // strict-primary-error
+            fn main() {}
+        ").unwrap(), Directives {
+            mode: Some(Mode::BuildFail),
+            strict_primary_error: true,
+            timeout: Some(Duration::from_secs(120)),
+            ignore: Some("needs a newer compiler".to_string()),
+            known_bug: Some("#123".to_string()),
+            ..Directives::default()
+        });
+        assert_eq!(parse_directives("\
+            // run-fail
+            // exit-code: 101
+            // panic-message: already borrowed: BorrowMutError
+        ").unwrap(), Directives {
+            mode: Some(Mode::RunFail),
+            exit_code: Some(101),
+            panic_message: Some("already borrowed: BorrowMutError".to_string()),
+            ..Directives::default()
+        });
+        // directives after the first line of code are ignored
+        assert_eq!(parse_directives("fn main() {}\n// build-fail").unwrap(), Directives::default());
+
+        let err = parse_directives("// build-fail: yes").unwrap_err().to_string();
+        assert!(err.contains("does not take a value"), "{}", err);
+        assert_eq!(parse_directives("// run-pass").unwrap().mode, Some(Mode::RunPass));
+        assert_eq!(parse_directives(r#"
+            // normalize-stderr: "\d+ bytes" -> "N bytes"
+            // normalize-stderr: "say \"hi\"" -> ""
+        "#).unwrap().normalize, vec![
+            (r"\d+ bytes".to_string(), "N bytes".to_string()),
+            (r#"say "hi""#.to_string(), String::new()),
+        ]);
+        let err = parse_directives(r#"// normalize-stderr: "a" => "b""#).unwrap_err().to_string();
+        assert!(err.contains("expected"), "{}", err);
+        let err = parse_directives(r#"// normalize-stderr: "a" -> "b"#).unwrap_err().to_string();
+        assert!(err.contains("unterminated"), "{}", err);
+        let err = parse_directives("// build-fail\n// check-pass").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+        let err = parse_directives("// timeout").unwrap_err().to_string();
+        assert!(err.contains("needs a value"), "{}", err);
+        let err = parse_directives("// exit-code: failure").unwrap_err().to_string();
+        assert!(err.contains("invalid exit code"), "{}", err);
+        let err = parse_directives("// known-bug:").unwrap_err().to_string();
+        assert!(err.contains("needs a value"), "{}", err);
+        let err = parse_directives("// timeout: 5 hours").unwrap_err().to_string();
+        assert!(err.contains("unknown unit"), "{}", err);
+        let err = parse_directives("// timeout: 18446744073709551615m").unwrap_err().to_string();
+        assert!(err.contains("too long"), "{}", err);
+        let err = parse_directives("// timeout: 10s\n// timeout: 20s").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+        let err = parse_directives("// ignore: slow\n// ignore: flaky").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+        let err = parse_directives("// known-bug: #1\n// known-bug: #2").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+        let err = parse_directives("// panic-message: a\n// panic-message: b").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+        let err = parse_directives("// exit-code: 1\n// exit-code: 2").unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+    }
+
+    #[test]
+    fn parses_fixes() {
+        let file = Path::new("test.rs");
+        assert_eq!(parse_fixes(file, "\
+            fn main() {
+                let x: u8 = \"oops\";  //~ ERROR mismatched types
+                // fixed: let x: u8 =
+                // fixed:     0;
+
+                let () = 0;
+                //~^ ERROR mismatched types
+                // fixed: let () = ();
+            }
+        ").unwrap(), vec![
+            Fix {
+                file: PathBuf::from("test.rs"),
+                line_num: 2,
+                code: "let x: u8 = 0;".to_string(),
+            },
+            Fix {
+                file: PathBuf::from("test.rs"),
+                line_num: 6,
+                code: "let () = ();".to_string(),
+            },
+        ]);
+
+        let err = parse_fixes(file, "// fixed: fn main() {}").unwrap_err().to_string();
+        assert!(err.contains("must follow the line it fixes"), "{}", err);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("60s").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("60").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
     }
 
     #[test]
     fn parses_patterns() {
         assert_eq!(pattern(1, "//~ eRrOr: message"), Pattern {
+            file: PathBuf::from("test.rs"),
             kind: Some(MessageKind::Error),
             matcher: Matcher::Msg("message".to_string()),
             line_num: 1,
+            annotation_line: 1,
         });
         assert_eq!(pattern(1, "//~ ERROR[E0001]"), Pattern {
+            file: PathBuf::from("test.rs"),
             kind: Some(MessageKind::Error),
             matcher: Matcher::Code("E0001".to_string()),
             line_num: 1,
+            annotation_line: 1,
         });
         assert_eq!(pattern(4, "//~^^^ ERROR[E0001]"), Pattern {
+            file: PathBuf::from("test.rs"),
             kind: Some(MessageKind::Error),
             matcher: Matcher::Code("E0001".to_string()),
             line_num: 1,
+            annotation_line: 4,
         });
         assert_eq!(patterns("\
                 //~ ERROR[E0001]\n\
                 //~|   note: massage   "), vec![
             Pattern {
+                file: PathBuf::from("test.rs"),
                 kind: Some(MessageKind::Error),
                 matcher: Matcher::Code("E0001".to_string()),
                 line_num: 1,
+                annotation_line: 1,
             },
             Pattern {
+                file: PathBuf::from("test.rs"),
                 kind: Some(MessageKind::Note),
                 matcher: Matcher::Msg("massage   ".to_string()),
                 line_num: 1,
+                annotation_line: 2,
             },
         ]);
         assert_eq!(patterns("\
@@ -307,14 +861,18 @@ mod tests {
                 //~^ ERROR[some code]\n\
                 //~|warn: massage"), vec![
             Pattern {
+                file: PathBuf::from("test.rs"),
                 kind: Some(MessageKind::Error),
                 matcher: Matcher::Code("some code".to_string()),
                 line_num: 1,
+                annotation_line: 2,
             },
             Pattern {
+                file: PathBuf::from("test.rs"),
                 kind: Some(MessageKind::Warning),
                 matcher: Matcher::Msg("massage".to_string()),
                 line_num: 1,
+                annotation_line: 3,
             },
         ]);
     }
@@ -0,0 +1,96 @@
+//! Runs child processes with an optional time limit.
+
+use wait_timeout::ChildExt;
+
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Runs `cmd` to completion and collects its output, like `Command::output`.
+///
+/// If `timeout` is given and the process doesn't exit within it, the process and all of its
+/// children are killed and `None` is returned.
+pub fn output_with_timeout(cmd: &mut Command, timeout: Option<Duration>) -> io::Result<Option<Output>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return cmd.output().map(Some),
+    };
+
+    cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    new_process_group(cmd);
+    let mut child = cmd.spawn()?;
+
+    // Read the pipes while waiting, or the child could block on a full pipe and never exit
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    match child.wait_timeout(timeout)? {
+        Some(status) => Ok(Some(Output {
+            status,
+            stdout: join_reader(stdout)?,
+            stderr: join_reader(stderr)?,
+        })),
+        None => {
+            info!("process {} timed out after {:?}, killing it", child.id(), timeout);
+            kill_process_tree(&mut child)?;
+            child.wait()?;
+            // Don't join the readers: They only finish once every process holding the pipes
+            // exited, which might take a while if one escaped the process group.
+            Ok(None)
+        }
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+fn join_reader(reader: JoinHandle<io::Result<Vec<u8>>>) -> io::Result<Vec<u8>> {
+    reader.join()
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "pipe reader thread panicked")))
+}
+
+/// Makes the process spawned by `cmd` the leader of a new process group, so that it can be killed
+/// along with its children.
+#[cfg(unix)]
+fn new_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    use libc;
+
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn new_process_group(_cmd: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_tree(child: &mut Child) -> io::Result<()> {
+    use libc;
+
+    // The child is the leader of its own process group, so this kills all of its descendants
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        child.kill()
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_tree(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
@@ -0,0 +1,351 @@
+//! Renders a readable report comparing the patterns of a test with the compiler's output.
+//!
+//! The report shows the source of the test around every line a pattern or diagnostic points at.
+//! Below each line, its patterns and diagnostics are listed and marked as matched, missing (a
+//! pattern without a matching diagnostic) or unexpected (a diagnostic without a matching pattern).
+//! Unexpected diagnostics are followed by their output as rendered by rustc.
+//!
+//! For missing patterns, the report looks for the diagnostic the pattern was probably meant to
+//! match, and suggests a corrected annotation: The usual causes are an annotation pointing at the
+//! wrong line and a message that was reworded.
+
+use annotate::pattern_for;
+use config::TestSettings;
+use json::Message;
+use parse::{Matcher, MessageKind, Pattern, TestExpectation};
+use runner::must_be_matched;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Number of source lines shown above and below each line that has entries.
+const CONTEXT_LINES: usize = 1;
+
+/// How far a diagnostic matching a missing pattern may be from the pattern's line to be suggested.
+const MAX_LINE_DISTANCE: usize = 3;
+
+/// How `similar` a message has to be to a missing message pattern to be suggested.
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// Whether a pattern or diagnostic has a counterpart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Matched,
+    Missing,
+    Unexpected,
+}
+
+/// A pattern or diagnostic listed below the line it points at.
+#[derive(Debug)]
+struct Entry<'a> {
+    line_num: usize,
+    status: Status,
+    text: String,
+    /// The diagnostic as rendered by rustc, shown for unexpected diagnostics.
+    rendered: Option<&'a str>,
+    /// A corrected annotation for a missing pattern.
+    suggestion: Option<String>,
+}
+
+/// Renders the report for the patterns in `expect` and the messages rustc emitted (`got`).
+///
+/// Files without patterns or relevant diagnostics are left out.
+pub fn render(expect: &TestExpectation, got: &[Message], settings: &TestSettings) -> String {
+    expect.files.iter().map(|file| {
+        // The report is only informative, so a file that went away is shown without source
+        let mut content = String::new();
+        let _ = File::open(file).and_then(|mut f| f.read_to_string(&mut content));
+        render_file(file, &content, &expect.expected_msgs, got, settings)
+    }).collect()
+}
+
+/// Renders the part of the report for `file`, whose content is `content`.
+fn render_file(file: &Path, content: &str, expected: &[Pattern], got: &[Message], settings: &TestSettings) -> String {
+    let entries = entries(file, expected, got, settings);
+    let last_line = match entries.last() {
+        Some(entry) => entry.line_num + CONTEXT_LINES,
+        None => return String::new(),
+    };
+    let width = last_line.to_string().len();
+    let lines = content.lines().collect::<Vec<_>>();
+    let source_line = |line_num: usize| {
+        let line = lines.get(line_num - 1).map_or("", |line| line.trim_right());
+        format!("{:>width$} | {}\n", line_num, line, width = width)
+    };
+
+    let mut out = format!("{}\n", file.display());
+    let mut shown_until = 0;
+    let mut entries = entries.iter().peekable();
+    while let Some(line_num) = entries.peek().map(|entry| entry.line_num) {
+        let first = cmp::max(line_num.saturating_sub(CONTEXT_LINES), shown_until + 1).max(1);
+        if shown_until != 0 && first > shown_until + 1 {
+            out.push_str("...\n");
+        }
+        for n in first..line_num + 1 {
+            out.push_str(&source_line(n));
+        }
+
+        while let Some(entry) = entries.peek().filter(|entry| entry.line_num == line_num).map(|e| *e) {
+            entries.next();
+            let label = match entry.status {
+                Status::Matched => "matched",
+                Status::Missing => "missing",
+                Status::Unexpected => "unexpected",
+            };
+            out.push_str(&format!("{:width$} = {}: {}\n", "", label, entry.text, width = width));
+            if let Some(ref suggestion) = entry.suggestion {
+                out.push_str(&format!("{:width$}   help: {}\n", "", suggestion, width = width));
+            }
+            if let Some(rendered) = entry.rendered {
+                for line in rendered.trim_right().lines() {
+                    out.push_str(&format!("{:width$}     {}\n", "", line, width = width));
+                }
+            }
+        }
+
+        // Show the lines below, unless they are shown above the next entry anyway
+        shown_until = line_num;
+        let next = entries.peek().map(|entry| entry.line_num);
+        for n in line_num + 1..cmp::min(line_num + CONTEXT_LINES, lines.len()) + 1 {
+            if next.map_or(false, |next| n >= next.saturating_sub(CONTEXT_LINES)) {
+                break;
+            }
+            out.push_str(&source_line(n));
+            shown_until = n;
+        }
+    }
+
+    out
+}
+
+/// Collects the entries for `file`, sorted by line.
+///
+/// Every pattern is either matched (listing all messages it matched) or missing. Messages that must
+/// be matched (see `must_be_matched`) but aren't are unexpected.
+fn entries<'a>(file: &Path, expected: &[Pattern], got: &'a [Message], settings: &TestSettings) -> Vec<Entry<'a>> {
+    let mut entries = Vec::new();
+
+    for pattern in expected.iter().filter(|pattern| pattern.file == file) {
+        let matched = got.iter()
+            .filter(|msg| pattern.matches(msg))
+            .map(|msg| describe_message(msg))
+            .collect::<Vec<_>>();
+        let entry = match matched.len() {
+            0 => Entry {
+                line_num: pattern.line_num,
+                status: Status::Missing,
+                text: describe_pattern(pattern),
+                rendered: None,
+                suggestion: suggest(pattern, expected, got),
+            },
+            n => Entry {
+                line_num: pattern.line_num,
+                status: Status::Matched,
+                text: if n == 1 {
+                    format!("{} (found {})", describe_pattern(pattern), matched[0])
+                } else {
+                    format!("{} (found {} messages: {})", describe_pattern(pattern), n, matched.join("; "))
+                },
+                rendered: None,
+                suggestion: None,
+            },
+        };
+        entries.push(entry);
+    }
+
+    for msg in got.iter()
+        .filter(|msg| msg.file == file && must_be_matched(msg, settings))
+        .filter(|msg| !expected.iter().any(|pattern| pattern.matches(msg))) {
+
+        entries.push(Entry {
+            line_num: msg.line_num,
+            status: Status::Unexpected,
+            text: describe_message(msg),
+            rendered: msg.rendered.as_ref().map(|rendered| &**rendered),
+            suggestion: None,
+        });
+    }
+
+    // The sort is stable, so patterns stay in front of unexpected messages on the same line
+    entries.sort_by_key(|entry| entry.line_num);
+    entries
+}
+
+/// Suggests a corrected annotation for `pattern`, which doesn't match any message in `got`.
+///
+/// Only messages not matched by another pattern are considered. If one of them matches `pattern`
+/// on a nearby line, the annotation is moved there. Otherwise, the most `similar` message on the
+/// pattern's line is suggested for a message pattern.
+fn suggest(pattern: &Pattern, expected: &[Pattern], got: &[Message]) -> Option<String> {
+    let candidates = got.iter()
+        .filter(|msg| msg.file == pattern.file && msg.kind == pattern.kind)
+        .filter(|msg| !expected.iter().any(|other| other.matches(msg)))
+        .collect::<Vec<_>>();
+    let distance = |msg: &Message| cmp::max(msg.line_num, pattern.line_num) - cmp::min(msg.line_num, pattern.line_num);
+
+    let nearest = candidates.iter()
+        .filter(|msg| pattern.matches_text(msg) && distance(msg) <= MAX_LINE_DISTANCE)
+        .min_by_key(|msg| distance(msg));
+    if let Some(msg) = nearest {
+        return Some(format!("expected on line {} but found on line {}; {}", pattern.line_num, msg.line_num,
+                            annotation_for(pattern.annotation_line, msg.line_num, &describe_pattern(pattern))));
+    }
+
+    let message = match pattern.matcher {
+        Matcher::Msg(ref message) => message,
+        Matcher::Code(_) => return None,
+    };
+    let mut similar = candidates.iter()
+        .filter(|msg| msg.line_num == pattern.line_num)
+        .map(|msg| (similarity(message, &msg.msg), msg))
+        .filter(|&(similarity, _)| similarity >= MIN_SIMILARITY)
+        .collect::<Vec<_>>();
+    // Prefer the most similar message, and the first one among equally similar messages
+    similar.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+    similar.first().map(|&(_, msg)| {
+        format!("found `{}` instead; {}", describe_message(msg),
+                annotation_for(pattern.annotation_line, msg.line_num, &pattern_for(msg)))
+    })
+}
+
+/// Describes how to write an annotation on `annotation_line` that points at `line_num`.
+fn annotation_for(annotation_line: usize, line_num: usize, pattern: &str) -> String {
+    if line_num <= annotation_line {
+        format!("use `//~{} {}`", "^".repeat(annotation_line - line_num), pattern)
+    } else {
+        // Annotations can't point at lines below them
+        format!("put `//~ {}` on line {}", pattern, line_num)
+    }
+}
+
+/// Returns the fraction of words in `pattern` that also occur in `message`.
+///
+/// This is 1 if the message merely gained a few words, and drops as words are changed or removed.
+fn similarity(pattern: &str, message: &str) -> f64 {
+    let words = message.split_whitespace().collect::<HashSet<_>>();
+    let pattern_words = pattern.split_whitespace().collect::<Vec<_>>();
+    if pattern_words.is_empty() {
+        return 0.0;
+    }
+
+    let common = pattern_words.iter().filter(|word| words.contains(*word)).count();
+    common as f64 / pattern_words.len() as f64
+}
+
+/// Formats `pattern` the way it is written in the test (eg. `error[E0308]`).
+pub fn describe_pattern(pattern: &Pattern) -> String {
+    match pattern.matcher {
+        Matcher::Code(ref code) => format!("{}[{}]", kind_name(pattern.kind), code),
+        Matcher::Msg(ref msg) => format!("{}: {}", kind_name(pattern.kind), msg),
+    }
+}
+
+/// Formats `msg` like rustc's headline (eg. `error[E0308]: mismatched types`).
+pub fn describe_message(msg: &Message) -> String {
+    match msg.code {
+        Some(ref code) => format!("{}[{}]: {}", kind_name(msg.kind), code, msg.msg),
+        None => format!("{}: {}", kind_name(msg.kind), msg.msg),
+    }
+}
+
+fn kind_name(kind: Option<MessageKind>) -> &'static str {
+    match kind {
+        Some(MessageKind::Error) => "error",
+        Some(MessageKind::Warning) => "warning",
+        Some(MessageKind::Note) => "note",
+        Some(MessageKind::Help) => "help",
+        Some(MessageKind::Suggestion) => "suggestion",
+        None => "message",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_source_view() {
+        let file = Path::new("test.rs");
+        let content = "\
+fn main() {
+    let () = 0;     //~ error[E0308]
+    let x = 1;
+    let y = 2;
+    let z = 3;
+    z = w;          //~ error: cannot assign twice
+}
+";
+        let expected = vec![
+            Pattern::error(2, Matcher::Code("E0308".to_string())),
+            Pattern::error(6, Matcher::Msg("cannot assign twice".to_string())),
+        ];
+        let error = |line_num, code, msg, rendered: &str| Message {
+            rendered: Some(rendered.to_string()),
+            ..Message::error(line_num, Some(code), msg)
+        };
+        let got = vec![
+            error(2, "E0308", "mismatched types", "error[E0308]: mismatched types\n"),
+            error(6, "E0425", "cannot find value `w` in this scope", "error[E0425]: cannot find value `w` in this scope\n --> test.rs:6:9\n"),
+        ];
+
+        assert_eq!(render_file(file, content, &expected, &got, &TestSettings::default()), "\
+test.rs
+1 | fn main() {
+2 |     let () = 0;     //~ error[E0308]
+  = matched: error[E0308] (found error[E0308]: mismatched types)
+3 |     let x = 1;
+...
+5 |     let z = 3;
+6 |     z = w;          //~ error: cannot assign twice
+  = missing: error: cannot assign twice
+  = unexpected: error[E0425]: cannot find value `w` in this scope
+      error[E0425]: cannot find value `w` in this scope
+       --> test.rs:6:9
+7 | }
+");
+    }
+
+    #[test]
+    fn suggests_corrections() {
+        let pattern = |line_num, annotation_line, matcher| Pattern {
+            annotation_line,
+            ..Pattern::error(line_num, matcher)
+        };
+        let got = vec![
+            Message::error(5, Some("E0308"), "mismatched types"),
+            Message::error(8, Some("E0499"), "cannot borrow `x` as mutable more than once at a time"),
+        ];
+
+        let off_by_one = pattern(4, 5, Matcher::Code("E0308".to_string()));
+        assert_eq!(suggest(&off_by_one, &[], &got).unwrap(),
+                   "expected on line 4 but found on line 5; use `//~ error[E0308]`");
+        let above = pattern(4, 4, Matcher::Msg("mismatched types".to_string()));
+        assert_eq!(suggest(&above, &[], &got).unwrap(),
+                   "expected on line 4 but found on line 5; put `//~ error: mismatched types` on line 5");
+        let below = pattern(6, 7, Matcher::Msg("mismatched".to_string()));
+        assert_eq!(suggest(&below, &[], &got).unwrap(),
+                   "expected on line 6 but found on line 5; use `//~^^ error: mismatched`");
+        let too_far = pattern(1, 1, Matcher::Code("E0308".to_string()));
+        assert_eq!(suggest(&too_far, &[], &got), None);
+
+        let reworded = pattern(8, 8, Matcher::Msg("cannot borrow `x` mutably more than once".to_string()));
+        assert_eq!(suggest(&reworded, &[], &got).unwrap(),
+                   "found `error[E0499]: cannot borrow `x` as mutable more than once at a time` instead; use `//~ error[E0499]`");
+        let unrelated = pattern(8, 9, Matcher::Msg("use of moved value".to_string()));
+        assert_eq!(suggest(&unrelated, &[], &got), None);
+
+        // Messages matched by other patterns are taken
+        let taken = vec![pattern(5, 5, Matcher::Code("E0308".to_string()))];
+        assert_eq!(suggest(&off_by_one, &taken, &got), None);
+    }
+
+    #[test]
+    fn computes_similarity() {
+        assert_eq!(similarity("mismatched types", "mismatched types"), 1.0);
+        assert_eq!(similarity("borrowed value does not live long enough", "`x` does not live long enough"), 5.0 / 7.0);
+        assert_eq!(similarity("use of moved value", "mismatched types"), 0.0);
+    }
+}
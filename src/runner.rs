@@ -1,22 +1,138 @@
 //! Runs the compiler and compares its output with the patterns in the compile-fail test.
 
 use Config;
+use batch;
+use config::{Mode, TestSettings};
 use compile::Blueprint;
+use discover::Test;
 use parse::{Pattern, MessageKind, TestExpectation};
-use json::{Message, parse_output};
-use status::TestStatus;
+use error::{self, Mismatch};
+use json::{Message, decode_output, parse_output, rendered_output, reported_error_count};
+use normalize::Normalizer;
+use process::output_with_timeout;
+use report::{self, describe_message, describe_pattern};
+use snapshot;
+use status::{Outcome, TestStatus};
+use variants;
 
+use num_cpus;
+
+use std::env;
 use std::error::Error;
-use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The result of comparing the patterns of a test with the messages output by rustc.
+#[derive(Debug)]
+pub struct Comparison<'a> {
+    /// Patterns that didn't match any message.
+    pub missing: Vec<&'a Pattern>,
+    /// Messages that had to be matched by a pattern (see `must_be_matched`), but weren't.
+    pub unexpected: Vec<&'a Message>,
+    /// Patterns that matched a message that is left over after pairing every pattern with a
+    /// different message, along with all messages they matched.
+    ///
+    /// These don't make the comparison fail, since rustc sometimes emits the same error twice, but
+    /// they are listed along with the other mismatches.
+    pub ambiguous: Vec<(&'a Pattern, Vec<&'a Message>)>,
+    /// If `strict_primary_error` is set and the first error isn't matched by the first pattern,
+    /// the first error and the first pattern.
+    pub wrong_primary_error: Option<(&'a Message, &'a Pattern)>,
+}
+
+impl<'a> Comparison<'a> {
+    /// Whether the messages match the patterns.
+    pub fn is_match(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.wrong_primary_error.is_none()
+    }
+
+    /// Returns all mismatches in the form reported to users, including ambiguous patterns (which
+    /// don't make the comparison fail on their own).
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        let missing = self.missing.iter().map(|pattern| Mismatch::Missing {
+            file: pattern.file.clone(),
+            line: pattern.line_num,
+            pattern: describe_pattern(pattern),
+        });
+        let unexpected = self.unexpected.iter().map(|msg| Mismatch::Unexpected {
+            file: msg.file.clone(),
+            line: msg.line_num,
+            message: describe_message(msg),
+        });
+        let wrong_primary_error = self.wrong_primary_error.iter().map(|&(first_error, first_pattern)| {
+            Mismatch::WrongPrimaryError {
+                file: first_error.file.clone(),
+                line: first_error.line_num,
+                message: describe_message(first_error),
+                pattern: describe_pattern(first_pattern),
+            }
+        });
+        let ambiguous = self.ambiguous.iter().map(|&(pattern, ref msgs)| Mismatch::Ambiguous {
+            file: pattern.file.clone(),
+            line: pattern.line_num,
+            pattern: describe_pattern(pattern),
+            messages: msgs.iter().map(|msg| describe_message(msg)).collect(),
+        });
+        missing.chain(unexpected).chain(ambiguous).chain(wrong_primary_error).collect()
+    }
+
+    /// Describes the ambiguous patterns, which are reported even if the test passes.
+    pub fn ambiguous_warnings(&self) -> Vec<String> {
+        self.ambiguous.iter().map(|&(pattern, ref msgs)| {
+            format!("pattern matches {} messages: {}:{}: {}",
+                    msgs.len(), pattern.file.display(), pattern.line_num, describe_pattern(pattern))
+        }).collect()
+    }
+}
+
+impl<'a> fmt::Display for Comparison<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for pattern in &self.missing {
+            writeln!(f, "message not found in compiler output: {}:{}: {}",
+                     pattern.file.display(), pattern.line_num, describe_pattern(pattern))?;
+        }
+        for msg in &self.unexpected {
+            writeln!(f, "unexpected {} in compiler output: {}:{}: {}",
+                     if msg.kind == Some(MessageKind::Warning) { "warning" } else { "error" },
+                     msg.file.display(), msg.line_num, describe_message(msg))?;
+        }
+        if !self.unexpected.is_empty() {
+            writeln!(f, "(all errors and warnings must be matched by a pattern in the test)")?;
+        }
+        for warning in self.ambiguous_warnings() {
+            writeln!(f, "{}", warning)?;
+        }
+        if let Some((first_error, first_pattern)) = self.wrong_primary_error {
+            writeln!(f, "the first error in compiler output must be matched by the first pattern (strict-primary-error is enabled)")?;
+            writeln!(f, "  first error: {}:{}: {}", first_error.file.display(), first_error.line_num, describe_message(first_error))?;
+            writeln!(f, "  first pattern: {}:{}: {}", first_pattern.file.display(), first_pattern.line_num, describe_pattern(first_pattern))?;
+        }
+        Ok(())
+    }
+}
 
 /// Compares messages parsed from a compile-fail test (`expected`) with messages output by rustc
-/// (`got`).
+/// (`got`), collecting all mismatches.
+///
+/// Errors that were not in `expected` are always unexpected, as are warnings if
+/// `settings.strict_warnings` is set. Notes and suggestions can be left out for brevity.
+/// Everything in `expected` must match an equivalent message (same kind and line) in `got`.
+/// Additionally, the message itself must be matched by the regex in `expected`.
 ///
-/// Errors and warnings that were not in `expected` always cause a fatal error, while notes and
-/// suggestions can be left out for brevity. Everything in `expected` must match an equivalent
-/// message (same kind and line) in `got`. Additionally, the message itself must be matched by the
-/// regex in `expected`.
-fn compare_messages(expected: &[Pattern], got: &[Message]) -> Result<(), Box<Error>> {
+/// If `settings.strict_primary_error` is set, the first error in `got` must also be matched by the
+/// first pattern in `expected`.
+///
+/// Returns an error if `expected` contains patterns that can't be matched yet.
+pub fn compare_messages<'a>(expected: &'a [Pattern], got: &'a [Message], settings: &TestSettings) -> Result<Comparison<'a>, Box<Error>> {
     // For now, disable matching anything but errors. It can be hard to reliably produce both an
     // error (which is needed to pass the test at all) and another message type.
     if let Some(non_error) = expected.iter()
@@ -25,67 +141,406 @@ fn compare_messages(expected: &[Pattern], got: &[Message]) -> Result<(), Box<Err
         return Err(format!("matching non-error messages is not yet supported ({:?})", non_error).into());
     }
 
-    // match everything in `expected` against `got` (ensures that we got everything we expected)
-    if let Some(not_found) = expected.iter()
-        .find(|pattern| !got.iter().any(|msg| pattern.matches(msg))) {
+    let mut comparison = Comparison {
+        missing: Vec::new(),
+        unexpected: Vec::new(),
+        ambiguous: Vec::new(),
+        wrong_primary_error: None,
+    };
 
-        return Err(format!("message not found in compiler output: {:?}", not_found).into());
+    // match everything in `expected` against `got` (ensures that we got everything we expected)
+    let paired = pair_messages(expected, got);
+    for pattern in expected {
+        let matched = got.iter().filter(|msg| pattern.matches(msg)).collect::<Vec<_>>();
+        let left_over = got.iter()
+            .zip(&paired)
+            .any(|(msg, paired)| paired.is_none() && pattern.matches(msg));
+        if matched.is_empty() {
+            comparison.missing.push(pattern);
+        } else if left_over {
+            comparison.ambiguous.push((pattern, matched));
+        }
     }
 
     // match all errors and warnings we `got` against `expected`
     // (ensures that all errors and warnings are expected)
-    if let Some(not_found) = got.iter()
-        .filter(|got| got.kind == Some(MessageKind::Error) || got.kind == Some(MessageKind::Warning))
-        .find(|got| !expected.iter().any(|pattern| pattern.matches( got))) {
+    comparison.unexpected = got.iter()
+        .filter(|got| must_be_matched(got, settings))
+        .filter(|got| !expected.iter().any(|pattern| pattern.matches(got)))
+        .collect();
 
-        return Err(format!("unexpected error or warning in compiler output (all errors and warnings must be matched by a pattern in the test): {:?}", not_found).into());
+    if settings.strict_primary_error {
+        let first_error = got.iter().find(|msg| msg.kind == Some(MessageKind::Error));
+        if let (Some(first_error), Some(first_pattern)) = (first_error, expected.first()) {
+            if !first_pattern.matches(first_error) {
+                comparison.wrong_primary_error = Some((first_error, first_pattern));
+            }
+        }
     }
 
-    Ok(())
+    Ok(comparison)
+}
+
+/// Pairs as many messages in `got` as possible with a different pattern in `expected` that matches
+/// them, so that repeated patterns can match repeated messages.
+///
+/// Returns the index of the pattern paired with each message.
+fn pair_messages(expected: &[Pattern], got: &[Message]) -> Vec<Option<usize>> {
+    /// Tries to pair the pattern at `index` with a message, moving patterns that are already paired
+    /// to other messages if necessary.
+    fn try_pair(index: usize, expected: &[Pattern], got: &[Message], visited: &mut [bool], paired: &mut [Option<usize>]) -> bool {
+        for (i, msg) in got.iter().enumerate() {
+            if visited[i] || !expected[index].matches(msg) {
+                continue;
+            }
+            visited[i] = true;
+            if paired[i].map_or(true, |other| try_pair(other, expected, got, visited, paired)) {
+                paired[i] = Some(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut paired = vec![None; got.len()];
+    for index in 0..expected.len() {
+        try_pair(index, expected, got, &mut vec![false; got.len()], &mut paired);
+    }
+    paired
+}
+
+/// Whether `msg` is an error, or a warning while `settings.strict_warnings` is set, and thus has to
+/// be matched by a pattern.
+pub fn must_be_matched(msg: &Message, settings: &TestSettings) -> bool {
+    msg.kind == Some(MessageKind::Error) ||
+        (settings.strict_warnings && msg.kind == Some(MessageKind::Warning))
 }
 
 /// Runs the compiler on compile-fail tests and compares the resulting output with the corresponding
 /// `TestExpectation`.
-pub fn run(config: &Config, blueprint: &Blueprint, tests: &[(PathBuf, TestExpectation)]) -> Result<(), Box<Error>> {
-    let mut status = TestStatus::new(config, tests.len());
+///
+/// If `config.batch` is set, tests are first compiled in batches (see the `batch` module). The
+/// remaining tests are run on `config.jobs` threads, each writing its artifacts to a subdirectory
+/// of `out_dir`. Results are still printed in the order of `tests`.
+///
+/// `invalid` contains the tests that couldn't be parsed, along with the error. They are reported
+/// as failures before the other tests.
+pub fn run(config: &Config, blueprint: &Blueprint, out_dir: &Path, tests: Vec<(Test, TestExpectation)>, invalid: Vec<(Test, error::Error)>, mut status: TestStatus) -> Result<(), error::Error> {
     status.print_header()?;
+    for (test, error) in invalid {
+        let message = error.to_string();
+        let annotation_errors = match error {
+            error::Error::Annotations(errors) => errors,
+            _ => Vec::new(),
+        };
+        status.print_test(&test.name, Outcome::Invalid(message, annotation_errors))?;
+    }
 
-    for &(ref path, ref expect) in tests.iter() {
-        status.print_test(&path.file_name().unwrap().to_string_lossy(), run_test(blueprint, (path, expect)))?;
+    let jobs = num_jobs(config);
+    info!("running {} tests on {} threads", tests.len(), jobs);
+
+    let num_tests = tests.len();
+    let mut results = tests.iter()
+        .map(|&(_, ref expect)| expect.directives.ignore.clone().map(|reason| (Outcome::Ignored(reason), Vec::new())))
+        .collect::<Vec<_>>();
+    // Tests passing in a batch would skip the verification of their fixes
+    if config.batch && !config.verify_fixes {
+        let passed = batch::run(blueprint, config.timeout, out_dir, &tests)
+            .map_err(|e| error::Error::Compiler(format!("compiling a batch of tests failed: {}", e)))?;
+        for index in passed {
+            results[index] = Some((Outcome::Passed, Vec::new()));
+        }
+    }
+
+    // Everything that isn't ignored and didn't already pass as part of a batch is compiled
+    // individually
+    let pending = Arc::new((0..num_tests).filter(|&i| results[i].is_none()).collect::<Vec<_>>());
+    let tests = Arc::new(tests);
+    let next_test = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..jobs.min(pending.len()) {
+        let tests = tests.clone();
+        let pending = pending.clone();
+        let next_test = next_test.clone();
+        let sender = sender.clone();
+        let mut blueprint = blueprint.clone();
+        let out_dir = out_dir.to_owned();
+        let default_timeout = config.timeout;
+        let verify_fixes = config.verify_fixes;
+
+        thread::spawn(move || loop {
+            let index = match pending.get(next_test.fetch_add(1, Ordering::SeqCst)) {
+                Some(&index) => index,
+                None => break,
+            };
+            let (ref test, ref expect) = tests[index];
+
+            // Give every test its own output directory so that artifacts don't collide
+            let test_out_dir = out_dir.join(index.to_string());
+            let timeout = expect.directives.timeout.or(default_timeout);
+            let mut warnings = Vec::new();
+            let outcome = fs::create_dir(&test_out_dir)
+                .map_err(|e| e.into())
+                .and_then(|()| {
+                    blueprint.set_out_dir(test_out_dir.clone());
+                    match run_test(&blueprint, &test.settings, timeout, (&test.path, expect), &mut warnings)? {
+                        Outcome::Passed if verify_fixes && test.settings.mode.expects_errors() => {
+                            variants::verify(&blueprint, &test.settings, timeout, (&test.path, expect), &test_out_dir)
+                        }
+                        outcome => Ok(outcome),
+                    }
+                })
+                .unwrap_or_else(|e| Outcome::Failed(e.to_string(), Vec::new()));
+            let outcome = match expect.directives.known_bug {
+                Some(ref issue) => known_bug_outcome(outcome, issue),
+                None => outcome,
+            };
+
+            if sender.send((index, outcome, warnings)).is_err() {
+                break;
+            }
+        });
+    }
+    drop(sender);
+
+    // Print results in order. Results of later tests are buffered until all earlier ones are in.
+    let mut next_to_print = 0;
+    let mut print_ready = |results: &mut Vec<Option<_>>, status: &mut TestStatus| {
+        while let Some((outcome, warnings)) = results.get_mut(next_to_print).and_then(Option::take) {
+            let name = &tests[next_to_print].0.name;
+            status.print_test(name, outcome)?;
+            status.add_warnings(name, warnings);
+            next_to_print += 1;
+        }
+        Ok::<_, io::Error>(())
+    };
+    print_ready(&mut results, &mut status)?;
+    for (index, outcome, warnings) in receiver {
+        results[index] = Some((outcome, warnings));
+        print_ready(&mut results, &mut status)?;
+    }
+
+    // The channel is only closed early if a thread panicked, in which case its test has no result
+    for index in next_to_print..num_tests {
+        let (outcome, warnings) = results[index].take().unwrap_or_else(|| {
+            (Outcome::Failed("test runner thread panicked".to_string(), Vec::new()), Vec::new())
+        });
+        status.print_test(&tests[index].0.name, outcome)?;
+        status.add_warnings(&tests[index].0.name, warnings);
     }
 
     status.print_result()?;
     status.into_global_result()
 }
 
-/// Runs a test, does not print to the console (but might log).
-fn run_test(blueprint: &Blueprint, (path, expect): (&Path, &TestExpectation)) -> Result<(), Box<Error>> {
-    let mut cmd = blueprint.build_command(path);
-    cmd.args(&["--error-format", "json"]);
-    debug!("running {:?}", cmd);
+/// Turns the outcome of a test marked as a known bug into what is reported.
+///
+/// Such a test is supposed to not pass, so that it passing means the bug was fixed. A timeout is
+/// still reported as such, since a hang isn't the documented bug.
+fn known_bug_outcome(outcome: Outcome, issue: &str) -> Outcome {
+    match outcome {
+        Outcome::Passed => Outcome::UnexpectedlyFixed(issue.to_string()),
+        Outcome::TimedOut(timeout) => Outcome::TimedOut(timeout),
+        outcome => {
+            info!("known bug {} still fails: {:?}", issue, outcome);
+            Outcome::KnownBug(issue.to_string())
+        }
+    }
+}
+
+/// Determines how many tests to run in parallel.
+///
+/// Uses `config.jobs` if set, falling back to `RUST_TEST_THREADS` and then to the number of CPUs.
+fn num_jobs(config: &Config) -> usize {
+    config.jobs
+        .or_else(|| env::var("RUST_TEST_THREADS").ok().and_then(|s| s.parse().ok()))
+        .unwrap_or_else(num_cpus::get)
+        .max(1)
+}
 
-    let output = cmd.output()?;
+/// Runs a test, does not print to the console (but might log).
+///
+/// Returns an error if the test failed. If rustc doesn't finish within `timeout`, it is killed and
+/// the test is reported as timed out. Problems that don't make the test fail are added to
+/// `warnings`.
+fn run_test(blueprint: &Blueprint, settings: &TestSettings, timeout: Option<Duration>, (path, expect): (&Path, &TestExpectation), warnings: &mut Vec<String>) -> Result<Outcome, Box<Error>> {
+    let output = match compile_test(blueprint, settings, &expect.entry_point, timeout)? {
+        Some(output) => output,
+        None => return Ok(Outcome::TimedOut(timeout.unwrap())),
+    };
 
     debug!("{} stdout bytes, {} stderr bytes", output.stdout.len(), output.stderr.len());
 
-    let filename = path.display().to_string();
-    let json = String::from_utf8(output.stderr).expect("rustc output wasn't utf-8");
+    let json = decode_output(output.stderr)?;
 
-    let msgs = parse_output(&filename, &json)?;
+    let normalizer = Normalizer::new(path, settings, blueprint.sysroot())?;
+    let mut msgs = parse_output(&expect.files, &json)?;
+    for msg in &mut msgs {
+        msg.msg = normalizer.apply(&msg.msg);
+        msg.rendered = msg.rendered.as_ref().map(|rendered| normalizer.apply(rendered));
+    }
     info!("expected msgs: {:#?}", expect.expected_msgs);
     info!("rustc msgs: {:#?}", msgs);
 
-    compare_messages(&expect.expected_msgs, &msgs).map_err(|e| {
-        // attach compiler output
-        format!("{}\n\nrustc output:\n{:#?}", e, msgs)
+    if settings.strict_primary_error {
+        warnings.extend(error_count_warning(&json, &msgs));
+    }
+
+    let comparison = compare_messages(&expect.expected_msgs, &msgs, settings)?;
+    if !comparison.is_match() {
+        // attach an annotated view of the test and the compiler output
+        let message = format!("{}\n{}", comparison, report::render(expect, &msgs, settings));
+        return Ok(Outcome::Failed(message, comparison.mismatches()));
+    }
+    warnings.extend(comparison.ambiguous_warnings());
+
+    if snapshot::is_needed(path) {
+        snapshot::check(path, &normalizer.apply(&rendered_output(&json)))?;
+    }
+
+    // We check this last so we can see all other errors first.
+    match settings.mode {
+        Mode::CompileFail | Mode::BuildFail => if output.status.success() {
+            return Err(format!("compilation of compile-fail test {} succeeded", path.display()).into());
+        },
+        Mode::CheckPass | Mode::RunPass | Mode::RunFail => if !output.status.success() {
+            return Err(format!("compilation of {:?} test {} failed\n\n{}", settings.mode, path.display(), report::render(expect, &msgs, settings)).into());
+        },
+    }
+
+    match settings.mode {
+        Mode::RunPass | Mode::RunFail => run_executable(blueprint, settings.mode, timeout, expect),
+        Mode::CompileFail | Mode::BuildFail | Mode::CheckPass => Ok(Outcome::Passed),
+    }
+}
+
+/// Returns a warning if the number of errors in `msgs` differs from the number rustc reported in
+/// `json`.
+///
+/// This happens when errors point outside of the test's files or have no location at all, which
+/// means that patterns can't match them.
+fn error_count_warning(json: &str, msgs: &[Message]) -> Option<String> {
+    let num_errors = msgs.iter().filter(|msg| msg.kind == Some(MessageKind::Error)).count();
+    match reported_error_count(json) {
+        Some(reported) if reported != num_errors => {
+            Some(format!("rustc reported {} errors, but {} of them point into the test", reported, num_errors))
+        }
+        _ => None,
+    }
+}
+
+/// Compiles the test with the crate root `entry_point`, making rustc output JSON diagnostics.
+///
+/// Returns `None` if rustc doesn't finish within `timeout`.
+pub fn compile_test(blueprint: &Blueprint, settings: &TestSettings, entry_point: &Path, timeout: Option<Duration>) -> io::Result<Option<Output>> {
+    let mut cmd = blueprint.build_command(entry_point, settings);
+    if entry_point.file_name() == Some(OsStr::new("lib.rs")) {
+        // Directory tests with a `lib.rs` are compiled as libraries, everything else is a binary.
+        cmd.args(&["--crate-type", "lib"]);
+    }
+    cmd.args(&["--error-format", "json"]);
+    debug!("running {:?}", cmd);
+
+    output_with_timeout(&mut cmd, timeout)
+}
+
+/// Runs the executable built for a run-pass or run-fail test and checks how it exited.
+///
+/// Run-pass tests must exit successfully. Run-fail tests must fail, with the exit code and panic
+/// message given by their directives (if any).
+fn run_executable(blueprint: &Blueprint, mode: Mode, timeout: Option<Duration>, expect: &TestExpectation) -> Result<Outcome, Box<Error>> {
+    let mut cmd = Command::new(blueprint.executable_path()?);
+    debug!("running {:?}", cmd);
+
+    let output = match output_with_timeout(&mut cmd, timeout)? {
+        Some(output) => output,
+        None => return Ok(Outcome::TimedOut(timeout.unwrap())),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
 
-        // Who even needs error-chain, quick-error, failure or any of that stuff?
-    })?;
+    let failure = if mode == Mode::RunPass {
+        if output.status.success() {
+            None
+        } else {
+            Some(format!("test executable exited with {}", output.status))
+        }
+    } else if output.status.success() {
+        Some("test executable exited successfully, but was expected to fail".to_string())
+    } else if expect.directives.exit_code.map_or(false, |code| output.status.code() != Some(code)) {
+        Some(format!("expected test executable to exit with code {}, but it exited with {}",
+                     expect.directives.exit_code.unwrap(), output.status))
+    } else if expect.directives.panic_message.as_ref().map_or(false, |msg| !stderr.contains(&**msg)) {
+        Some(format!("expected panic message not found in test executable output: {:?}",
+                     expect.directives.panic_message.as_ref().unwrap()))
+    } else {
+        None
+    };
 
-    if output.status.success() {
-        // We check this last so we can see all other errors first.
-        return Err(format!("compilation of compile-fail test {} succeeded", path.display()).into());
+    match failure {
+        // attach the program's output
+        Some(failure) => Err(format!(
+            "{}\n\nstdout:\n{}\nstderr:\n{}",
+            failure,
+            String::from_utf8_lossy(&output.stdout),
+            stderr,
+        ).into()),
+        None => Ok(Outcome::Passed),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse::Matcher;
+    use std::path::PathBuf;
+
+    #[test]
+    fn collects_all_mismatches() {
+        let pattern = |line_num, code: &str| Pattern::error(line_num, Matcher::Code(code.to_string()));
+        let error = |line_num, code| Message::error(line_num, Some(code), "message");
+        let expected = vec![pattern(1, "E0308"), pattern(2, "E0308"), pattern(3, "E0425"), pattern(4, "E0499")];
+        let got = vec![error(1, "E0308"), error(3, "E0425"), error(3, "E0425"), error(5, "E0382"), error(6, "E0384")];
+
+        let comparison = compare_messages(&expected, &got, &TestSettings::default()).unwrap();
+        assert!(!comparison.is_match());
+        assert_eq!(comparison.missing, vec![&expected[1], &expected[3]]);
+        assert_eq!(comparison.unexpected.iter().map(|msg| msg.line_num).collect::<Vec<_>>(), vec![5, 6]);
+        assert_eq!(comparison.ambiguous.len(), 1);
+        assert_eq!(comparison.ambiguous[0].0, &expected[2]);
+        assert_eq!(comparison.ambiguous[0].1.len(), 2);
+        assert!(comparison.mismatches().contains(&Mismatch::Ambiguous {
+            file: PathBuf::from("test.rs"),
+            line: 3,
+            pattern: "error[E0425]".to_string(),
+            messages: vec!["error[E0425]: message".to_string(); 2],
+        }));
+
+        let comparison = compare_messages(&expected[..1], &got[..1], &TestSettings::default()).unwrap();
+        assert!(comparison.is_match());
 
-    Ok(())
+        // Repeated patterns are paired with repeated messages
+        let repeated = vec![pattern(3, "E0425"), pattern(3, "E0425")];
+        let comparison = compare_messages(&repeated, &got[1..3], &TestSettings::default()).unwrap();
+        assert!(comparison.is_match());
+        assert!(comparison.ambiguous.is_empty());
+    }
+
+    #[test]
+    fn reports_known_bugs() {
+        let outcome = |outcome| format!("{:?}", known_bug_outcome(outcome, "#1"));
+        assert_eq!(outcome(Outcome::Passed), "UnexpectedlyFixed(\"#1\")");
+        assert_eq!(outcome(Outcome::Failed("mismatch".to_string(), Vec::new())), "KnownBug(\"#1\")");
+        assert_eq!(outcome(Outcome::TimedOut(Duration::from_secs(1))), "TimedOut(1s)");
+    }
+
+    #[test]
+    fn warns_about_errors_outside_the_test() {
+        let json = r#"{"message":"aborting due to 2 previous errors","code":null,"level":"error","spans":[],"children":[],"rendered":null}"#;
+        let error = || Message::error(1, None, "message");
+        assert_eq!(
+            error_count_warning(json, &[error()]),
+            Some("rustc reported 2 errors, but 1 of them point into the test".to_string())
+        );
+        assert_eq!(error_count_warning(json, &[error(), error()]), None);
+    }
 }
@@ -0,0 +1,167 @@
+//! Compares rustc's human-readable output with a `.stderr` snapshot stored next to the test.
+//!
+//! Snapshots are optional. They are only checked for tests that have one. If `COMPILE_FAIL_BLESS=1`
+//! is set, existing snapshots are rewritten with the current output instead, which makes changes to
+//! diagnostics show up in code review. A snapshot is added by creating an empty `.stderr` file and
+//! blessing it.
+
+use diff;
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the environment variable that makes snapshots get rewritten instead of compared.
+const BLESS_VAR: &'static str = "COMPILE_FAIL_BLESS";
+
+/// Number of unchanged lines shown around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Returns the path of the snapshot belonging to the test at `path`.
+///
+/// For a single-file test `foo.rs`, this is `foo.stderr`. For a directory test `foo`, this is
+/// `foo.stderr` next to the directory.
+pub fn snapshot_path(path: &Path) -> PathBuf {
+    path.with_extension("stderr")
+}
+
+/// Determines whether snapshots should be rewritten (`COMPILE_FAIL_BLESS=1`).
+fn is_blessing() -> bool {
+    env::var(BLESS_VAR).map(|value| value == "1").unwrap_or(false)
+}
+
+/// Whether the test at `path` has a snapshot, so that its rendered output needs to be checked or
+/// blessed.
+pub fn is_needed(path: &Path) -> bool {
+    snapshot_path(path).is_file()
+}
+
+/// Compares the rendered compiler output for the test at `path` with its snapshot.
+///
+/// When blessing, the snapshot is overwritten instead.
+pub fn check(path: &Path, rendered: &str) -> Result<(), Box<Error>> {
+    let snapshot_path = snapshot_path(path);
+    let actual = normalize(rendered);
+
+    if is_blessing() {
+        info!("blessing {}", snapshot_path.display());
+        File::create(&snapshot_path)?.write_all(actual.as_bytes())?;
+        return Ok(());
+    }
+
+    if !snapshot_path.is_file() {
+        return Ok(());
+    }
+
+    let mut expected = String::new();
+    File::open(&snapshot_path)?.read_to_string(&mut expected)?;
+    let expected = normalize(&expected);
+    if expected != actual {
+        return Err(format!(
+            "compiler output differs from {} (rerun with {}=1 to update it):\n\n{}",
+            snapshot_path.display(), BLESS_VAR, unified_diff("expected", &expected, "actual", &actual)
+        ).into());
+    }
+
+    Ok(())
+}
+
+/// Removes differences that don't matter: Line endings and trailing whitespace.
+fn normalize(output: &str) -> String {
+    let mut normalized = output.lines()
+        .map(|line| line.trim_right())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let trimmed_len = normalized.trim_right().len();
+    normalized.truncate(trimmed_len);
+    if !normalized.is_empty() {
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Creates a unified diff (like `diff -u`) turning `old` into `new`.
+pub fn unified_diff(old_name: &str, old: &str, new_name: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let lines = diff::slice(&old, &new);
+    let is_change = |line: &diff::Result<&&str>| match *line {
+        diff::Result::Both(..) => false,
+        _ => true,
+    };
+
+    // Group changes that are close together into hunks, each with some surrounding context
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in lines.iter().enumerate().filter(|&(_, line)| is_change(line)) {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(lines.len());
+        if let Some(last) = hunks.last_mut() {
+            if start <= last.1 {
+                last.1 = end;
+                continue;
+            }
+        }
+        hunks.push((start, end));
+    }
+
+    let count_old = |lines: &[diff::Result<&&str>]| lines.iter()
+        .filter(|line| match **line { diff::Result::Right(_) => false, _ => true })
+        .count();
+    let count_new = |lines: &[diff::Result<&&str>]| lines.iter()
+        .filter(|line| match **line { diff::Result::Left(_) => false, _ => true })
+        .count();
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n",
+                              count_old(&lines[..start]) + 1, count_old(&lines[start..end]),
+                              count_new(&lines[..start]) + 1, count_new(&lines[start..end])));
+        for line in &lines[start..end] {
+            let (prefix, text) = match *line {
+                diff::Result::Left(text) => ('-', text),
+                diff::Result::Right(text) => ('+', text),
+                diff::Result::Both(text, _) => (' ', text),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_output() {
+        assert_eq!(normalize(""), "");
+        assert_eq!(normalize("\n\n"), "");
+        assert_eq!(normalize("error: oops  \r\n  |\r\n\n"), "error: oops\n  |\n");
+    }
+
+    #[test]
+    fn creates_unified_diff() {
+        let expected = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let actual = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(unified_diff("expected", expected, "actual", actual), "\
+--- expected
++++ actual
+@@ -1,5 +1,5 @@
+ a
+-b
++B
+ c
+ d
+ e
+@@ -8,3 +8,4 @@
+ h
+ i
+ j
++k
+");
+    }
+}
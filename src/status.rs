@@ -1,32 +1,31 @@
 //! Test progress reporting.
 
 use Config;
+use error::{Error, Mismatch, TestFailure};
+use parse::AnnotationError;
 
 use termcolor::{ColorChoice, StandardStream, WriteColor, Color, ColorSpec};
 use std::io::{self, Write};
-use std::fmt::Display;
-use std::error::Error;
-use std::thread::panicking;
-use std::str;
+use std::time::{Duration, Instant};
 
 enum Out {
     Console(StandardStream),
-    /// Buffers output instead of printing.
-    Quiet(Vec<u8>),
+    /// Discards all output.
+    Quiet,
 }
 
 impl Write for Out {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match *self {
             Out::Console(ref mut s) => s.write(buf),
-            Out::Quiet(ref mut b) => b.write(buf),
+            Out::Quiet => Ok(buf.len()),
         }
     }
 
     fn flush(&mut self) -> io::Result<()> {
         match *self {
             Out::Console(ref mut s) => s.flush(),
-            Out::Quiet(ref mut b) => b.flush(),
+            Out::Quiet => Ok(()),
         }
     }
 }
@@ -35,116 +34,289 @@ impl WriteColor for Out {
     fn supports_color(&self) -> bool {
         match *self {
             Out::Console(ref s) => s.supports_color(),
-            Out::Quiet(_) => false,
+            Out::Quiet => false,
         }
     }
 
     fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
         match *self {
             Out::Console(ref mut s) => s.set_color(spec),
-            Out::Quiet(_) => Ok(()),
+            Out::Quiet => Ok(()),
         }
     }
 
     fn reset(&mut self) -> io::Result<()> {
         match *self {
             Out::Console(ref mut s) => s.reset(),
-            Out::Quiet(_) => Ok(()),
+            Out::Quiet => Ok(()),
         }
     }
 }
 
-pub struct TestStatus<E> {
+/// Output format, selected by libtest's `--format` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One line per test.
+    Pretty,
+    /// One character per test.
+    Terse,
+    /// One JSON object per line, compatible with libtest's (unstable) JSON output.
+    Json,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Pretty
+    }
+}
+
+/// The result of running a single test.
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    /// The test failed, with a description of the failure and the mismatches between its patterns
+    /// and the compiler output (if that's why it failed).
+    Failed(String, Vec<Mismatch>),
+    /// The compiler didn't finish within the time limit.
+    TimedOut(Duration),
+    /// The test wasn't run because of an `// ignore` directive with this reason.
+    Ignored(String),
+    /// The test couldn't be parsed. Holds the description of the problem and the malformed
+    /// annotations, if that's what it was.
+    Invalid(String, Vec<AnnotationError>),
+    /// The test didn't pass, but has a `// known-bug` directive referring to this issue.
+    KnownBug(String),
+    /// The test passed even though it has a `// known-bug` directive referring to this issue.
+    UnexpectedlyFixed(String),
+}
+
+impl Outcome {
+    /// Returns a description of why the test failed, or `None` if it didn't.
+    fn failure(&self) -> Option<String> {
+        match *self {
+            Outcome::Passed | Outcome::Ignored(_) | Outcome::KnownBug(_) => None,
+            Outcome::Failed(ref err, _) | Outcome::Invalid(ref err, _) => Some(err.clone()),
+            Outcome::TimedOut(timeout) => Some(format!("compiler timed out after {}", format_duration(timeout))),
+            Outcome::UnexpectedlyFixed(ref issue) => Some(format!(
+                "test matches all of its patterns, but is marked as known bug {} (remove the `// known-bug` directive if the bug was fixed)",
+                issue
+            )),
+        }
+    }
+}
+
+pub struct TestStatus {
     out: Out,
-    errors: Vec<(String, E)>,
+    format: Format,
+    errors: Vec<(String, Outcome)>,
+    /// Problems with tests that don't make them fail, along with the name of the test.
+    warnings: Vec<(String, String)>,
     num_tests: usize,
     num_passed: usize,
-    defused: bool,
+    num_ignored: usize,
+    num_known_bugs: usize,
+    num_filtered_out: usize,
+    start: Instant,
 }
 
-impl<E> TestStatus<E> {
-    pub fn new(config: &Config, num_tests: usize) -> Self {
+impl TestStatus {
+    pub fn new(config: &Config, format: Format, num_tests: usize, num_filtered_out: usize) -> Self {
         Self {
             out: if config.no_console_output {
-                Out::Quiet(Vec::new())
+                Out::Quiet
             } else {
                 Out::Console(StandardStream::stdout(ColorChoice::Auto))
             },
+            format,
             errors: Vec::new(),
+            warnings: Vec::new(),
             num_tests,
             num_passed: 0,
-            defused: false,
+            num_ignored: 0,
+            num_known_bugs: 0,
+            num_filtered_out,
+            start: Instant::now(),
         }
     }
 
     pub fn print_header(&mut self) -> io::Result<()> {
-        writeln!(self.out, "running {} compile-fail test{}",
-                 self.num_tests,
-                 if self.num_tests == 1 { "" } else { "s" })
+        match self.format {
+            Format::Pretty | Format::Terse => {
+                writeln!(self.out, "running {} compile-fail test{}",
+                         self.num_tests,
+                         if self.num_tests == 1 { "" } else { "s" })
+            }
+            Format::Json => {
+                writeln!(self.out, "{}", json!({
+                    "type": "suite",
+                    "event": "started",
+                    "test_count": self.num_tests,
+                }))
+            }
+        }
     }
 
-    /// Prints the short result of a single test (passed / failed).
-    pub fn print_test<T>(&mut self, name: &str, result: Result<T, E>) -> io::Result<()> {
-        write!(self.out, "test {} ... ", name)?;
-        self.colored_status(result.is_ok())?;
-        writeln!(self.out)?;
+    /// Prints the short result of a single test (passed / failed / timed out / ignored / ...).
+    pub fn print_test(&mut self, name: &str, outcome: Outcome) -> io::Result<()> {
+        match self.format {
+            Format::Pretty => {
+                write!(self.out, "test {} ... ", name)?;
+                match outcome {
+                    Outcome::Passed => self.colored(Color::Green, "ok")?,
+                    Outcome::Failed(..) | Outcome::Invalid(..) => self.colored(Color::Red, "FAILED")?,
+                    Outcome::TimedOut(_) => self.colored(Color::Red, "TIMED OUT")?,
+                    Outcome::Ignored(ref reason) => {
+                        self.colored(Color::Yellow, "ignored")?;
+                        write!(self.out, ", {}", reason)?;
+                    }
+                    Outcome::KnownBug(ref issue) => {
+                        self.colored(Color::Yellow, "known bug")?;
+                        write!(self.out, " {}", issue)?;
+                    }
+                    Outcome::UnexpectedlyFixed(_) => self.colored(Color::Red, "UNEXPECTEDLY FIXED")?,
+                }
+                writeln!(self.out)?;
+            }
+            Format::Terse => {
+                match outcome {
+                    Outcome::Passed => self.colored(Color::Green, ".")?,
+                    Outcome::Ignored(_) => self.colored(Color::Yellow, "i")?,
+                    Outcome::KnownBug(_) => self.colored(Color::Yellow, "k")?,
+                    Outcome::Failed(..) | Outcome::Invalid(..) | Outcome::TimedOut(_) | Outcome::UnexpectedlyFixed(_) => {
+                        self.colored(Color::Red, "F")?
+                    }
+                }
+                self.out.flush()?;
+            }
+            Format::Json => {
+                writeln!(self.out, "{}", json!({ "type": "test", "event": "started", "name": name }))?;
+                let event = match outcome {
+                    Outcome::Passed => json!({ "type": "test", "name": name, "event": "ok" }),
+                    Outcome::Ignored(ref reason) => json!({
+                        "type": "test",
+                        "name": name,
+                        "event": "ignored",
+                        "message": reason,
+                    }),
+                    Outcome::KnownBug(ref issue) => json!({
+                        "type": "test",
+                        "name": name,
+                        "event": "ok",
+                        "known_bug": issue,
+                    }),
+                    Outcome::Failed(..) | Outcome::Invalid(..) | Outcome::TimedOut(_) | Outcome::UnexpectedlyFixed(_) => json!({
+                        "type": "test",
+                        "name": name,
+                        "event": "failed",
+                        "stdout": outcome.failure(),
+                    }),
+                };
+                writeln!(self.out, "{}", event)?;
+            }
+        }
 
-        if let Err(e) = result {
-            self.errors.push((name.to_string(), e));
-        } else {
-            self.num_passed += 1;
+        match outcome {
+            Outcome::Passed => self.num_passed += 1,
+            Outcome::Ignored(_) => self.num_ignored += 1,
+            Outcome::KnownBug(_) => self.num_known_bugs += 1,
+            _ => self.errors.push((name.to_string(), outcome)),
         }
 
         Ok(())
     }
 
-    pub fn print_result(&mut self) -> io::Result<()>
-        where E: Display {
+    /// Records `warnings` about the test called `name`. They are printed along with the result.
+    ///
+    /// Without console output, they are logged instead, so that they don't get lost.
+    pub fn add_warnings(&mut self, name: &str, warnings: Vec<String>) {
+        if let Out::Quiet = self.out {
+            for warning in &warnings {
+                warn!("{}: {}", name, warning);
+            }
+        }
+        self.warnings.extend(warnings.into_iter().map(|warning| (name.to_string(), warning)));
+    }
 
+    pub fn print_result(&mut self) -> io::Result<()> {
+        let num_timed_out = self.errors.iter()
+            .filter(|&&(_, ref outcome)| if let Outcome::TimedOut(_) = *outcome { true } else { false })
+            .count();
+
+        if self.format == Format::Json {
+            let elapsed = self.start.elapsed();
+            return writeln!(self.out, "{}", json!({
+                "type": "suite",
+                "event": if self.errors.is_empty() { "ok" } else { "failed" },
+                "passed": self.num_passed,
+                "failed": self.errors.len(),
+                "ignored": self.num_ignored,
+                "known_bugs": self.num_known_bugs,
+                "measured": 0,
+                "filtered_out": self.num_filtered_out,
+                "exec_time": elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9,
+                "warnings": self.warnings.iter()
+                    .map(|&(ref name, ref warning)| format!("{}: {}", name, warning))
+                    .collect::<Vec<_>>(),
+            }));
+        }
+
+        if self.format == Format::Terse {
+            writeln!(self.out)?;
+        }
+        for (name, warning) in self.warnings.clone() {
+            self.colored(Color::Yellow, "warning")?;
+            writeln!(self.out, ": {}: {}", name, warning)?;
+        }
         write!(self.out, "test result: ")?;
-        let success = self.errors.is_empty();
-        self.colored_status(success)?;
-        writeln!(self.out, ". {} passed; {} failed", self.num_passed, self.errors.len())?;
+        if self.errors.is_empty() {
+            self.colored(Color::Green, "ok")?;
+        } else {
+            self.colored(Color::Red, "FAILED")?;
+        }
+        write!(self.out, ". {} passed; {} failed", self.num_passed, self.errors.len())?;
+        if num_timed_out > 0 {
+            write!(self.out, " ({} timed out)", num_timed_out)?;
+        }
+        if self.num_ignored > 0 {
+            write!(self.out, "; {} ignored", self.num_ignored)?;
+        }
+        if self.num_known_bugs > 0 {
+            write!(self.out, "; {} known bug{}", self.num_known_bugs, if self.num_known_bugs == 1 { "" } else { "s" })?;
+        }
+        if self.num_filtered_out > 0 {
+            write!(self.out, "; {} filtered out", self.num_filtered_out)?;
+        }
+        writeln!(self.out)?;
         writeln!(self.out)?;
 
-        for &(ref name, ref err) in self.errors.iter() {
+        for &(ref name, ref outcome) in self.errors.iter() {
             writeln!(self.out, "---- test {} ----", name)?;
-            writeln!(self.out, "{}", err)?;
+            writeln!(self.out, "{}", outcome.failure().unwrap_or_default())?;
             writeln!(self.out)?;
         }
 
         Ok(())
     }
 
-    /// Turns this `TestStatus` into a summarizing result that is `Ok` if all tests passed and `Err`
-    /// if at least one test failed.
-    ///
-    /// This method must be called or the `Drop` impl of `TestStatus` will panic.
-    pub fn into_global_result(mut self) -> Result<(), Box<Error>> {
-        self.defused = true;
+    /// Turns this `TestStatus` into a summarizing result that is `Ok` if all tests passed and
+    /// `Error::TestsFailed` if at least one test failed.
+    pub fn into_global_result(mut self) -> Result<(), Error> {
         if self.errors.is_empty() {
-            Ok(())
-        } else {
-            let msg = match self.out {
-                Out::Console(_) => {
-                    // We already printed everything to the console, a summary is enough
-                    String::new()
-                }
-                Out::Quiet(ref b) => {
-                    format!("{}\n\n", str::from_utf8(b).expect("produced non-utf8 output"))
-                }
-            };
-
-
-            Err(format!("{}{} compile-fail tests failed", msg, self.errors.len()).into())
+            return Ok(());
         }
+
+        Err(Error::TestsFailed(self.errors.drain(..).map(|(name, outcome)| {
+            let message = outcome.failure().unwrap_or_default();
+            let (mismatches, annotation_errors) = match outcome {
+                Outcome::Failed(_, mismatches) => (mismatches, Vec::new()),
+                Outcome::Invalid(_, annotation_errors) => (Vec::new(), annotation_errors),
+                _ => (Vec::new(), Vec::new()),
+            };
+            TestFailure { name, message, mismatches, annotation_errors }
+        }).collect()))
     }
 
-    fn colored_status(&mut self, pass: bool) -> io::Result<()> {
-        let (color, msg) = match pass {
-            true => (Color::Green, "ok"),
-            false => (Color::Red, "FAILED"),
-        };
+    fn colored(&mut self, color: Color, msg: &str) -> io::Result<()> {
         let _ = self.out.set_color(&ColorSpec::new().set_fg(Some(color)));
         write!(self.out, "{}", msg)?;
         let _ = self.out.reset();
@@ -152,10 +324,12 @@ impl<E> TestStatus<E> {
     }
 }
 
-impl<E> Drop for TestStatus<E> {
-    fn drop(&mut self) {
-        if !panicking() {
-            assert!(self.defused, "TestStatus::into_global_result was not called");
-        }
+/// Formats a duration in seconds (eg. `1.5s`).
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000);
+    if millis % 1000 == 0 {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{}s", millis as f64 / 1000.0)
     }
 }
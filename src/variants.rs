@@ -0,0 +1,120 @@
+//! Verifies that every error pattern is caused by the line it points at.
+//!
+//! A compile-fail test can pass because of an unrelated error that happens to be reported on the
+//! annotated line. To rule that out, a variant of the test is compiled for every line with a
+//! pattern, in which that line is replaced by its `// fixed:` alternative (or blanked out if it has
+//! none). The errors matched by the line's patterns must not be reported for the variant.
+//!
+//! If the variant has errors that no pattern matches, they may have made rustc stop before it got
+//! to the errors of the line, so the variant proves nothing and verification fails.
+
+use config::TestSettings;
+use compile::Blueprint;
+use parse::{MessageKind, TestExpectation};
+use json::{decode_output, parse_output};
+use normalize::Normalizer;
+use report::{self, describe_message, describe_pattern};
+use runner::compile_test;
+use status::Outcome;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Compiles a variant of the test at `path` for each annotated line.
+///
+/// Variants are written to subdirectories of `out_dir`. Returns an error describing the first
+/// pattern that still matches after its line was fixed, or the first variant with new errors.
+pub fn verify(blueprint: &Blueprint, settings: &TestSettings, timeout: Option<Duration>, (path, expect): (&Path, &TestExpectation), out_dir: &Path) -> Result<Outcome, Box<Error>> {
+    let mut lines = expect.expected_msgs.iter()
+        .map(|pattern| (&pattern.file, pattern.line_num))
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+
+    // Files keep their path relative to the test, so that `mod` declarations still work
+    let root = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(Path::new(""))
+    };
+
+    let normalizer = Normalizer::new(path, settings, blueprint.sysroot())?;
+    for (index, (file, line_num)) in lines.into_iter().enumerate() {
+        let fix = expect.fixes.iter().find(|fix| fix.file == *file && fix.line_num == line_num);
+        let variant_dir = out_dir.join(format!("variant-{}", index));
+        debug!("verifying line {} of {} in {}", line_num, file.display(), variant_dir.display());
+
+        let mut variant_files = Vec::new();
+        for source in &expect.files {
+            let mut content = String::new();
+            File::open(source)?.read_to_string(&mut content)?;
+            if source == file {
+                content = replace_line(&content, line_num, fix.map_or("", |fix| &*fix.code));
+            }
+
+            let target = variant_dir.join(source.strip_prefix(root)?);
+            fs::create_dir_all(target.parent().unwrap())?;
+            File::create(&target)?.write_all(content.as_bytes())?;
+            variant_files.push(target);
+        }
+
+        let mut blueprint = blueprint.clone();
+        blueprint.set_out_dir(variant_dir.clone());
+        let entry_point = variant_dir.join(expect.entry_point.strip_prefix(root)?);
+        let output = match compile_test(&blueprint, settings, &entry_point, timeout)? {
+            Some(output) => output,
+            None => return Ok(Outcome::TimedOut(timeout.unwrap())),
+        };
+
+        let json = decode_output(output.stderr)?;
+        let mut msgs = parse_output(&variant_files, &json)?;
+        for msg in &mut msgs {
+            // Make the messages point at the original files again
+            msg.file = original_path(&expect.files, &variant_files, &msg.file);
+            msg.msg = normalizer.apply(&msg.msg);
+        }
+
+        let action = if fix.is_some() { "fixing" } else { "removing" };
+        if let Some(pattern) = expect.expected_msgs.iter()
+            .filter(|pattern| pattern.file == *file && pattern.line_num == line_num)
+            .find(|pattern| msgs.iter().any(|msg| pattern.matches(msg))) {
+
+            return Err(format!(
+                "pattern still matches after {} line {} of {}, it might match an unrelated error: {}\n\n{}",
+                action, line_num, file.display(), describe_pattern(pattern), report::render(expect, &msgs, settings)
+            ).into());
+        }
+
+        if let Some(new_error) = msgs.iter()
+            .filter(|msg| msg.kind == Some(MessageKind::Error))
+            .find(|msg| !expect.expected_msgs.iter().any(|pattern| pattern.matches(msg))) {
+
+            return Err(format!(
+                "verification is inconclusive, since {} line {} of {} causes a new error (add a `// fixed:` comment with code that compiles): {}:{}: {}\n\n{}",
+                action, line_num, file.display(), new_error.file.display(), new_error.line_num,
+                describe_message(new_error), report::render(expect, &msgs, settings)
+            ).into());
+        }
+    }
+
+    Ok(Outcome::Passed)
+}
+
+/// Replaces line `line_num` of `content` with `replacement`, keeping the other lines in place.
+fn replace_line(content: &str, line_num: usize, replacement: &str) -> String {
+    content.lines()
+        .enumerate()
+        .map(|(i, line)| if i + 1 == line_num { replacement } else { line })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Maps a path in `variant_files` to the corresponding one in `files`.
+fn original_path(files: &[PathBuf], variant_files: &[PathBuf], path: &Path) -> PathBuf {
+    variant_files.iter()
+        .position(|variant| variant == path)
+        .map_or_else(|| path.to_path_buf(), |i| files[i].clone())
+}
@@ -0,0 +1,108 @@
+//! Tests the functions suggesting and rewriting `//~` annotations.
+
+extern crate compile_fail;
+
+use compile_fail::*;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn config() -> Config {
+    Config {
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    }
+}
+
+/// Copies `fixture` into a new temporary directory, so that it can be edited.
+fn copy_to_temp_dir(fixture: &str) -> PathBuf {
+    let fixture = Path::new(fixture);
+    let name = fixture.file_stem().unwrap().to_string_lossy();
+    let dir = env::temp_dir().join(format!("compile-fail-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    fs::copy(fixture, dir.join(fixture.file_name().unwrap())).unwrap();
+    dir
+}
+
+fn read(path: &Path) -> String {
+    let mut content = String::new();
+    File::open(path).unwrap().read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn bless_rewrites_annotations() {
+    let dir = copy_to_temp_dir("tests/bless/outdated.rs");
+    let c = Config {
+        cfail_paths: vec![dir.clone()],
+        verify_fixes: true,
+        ..config()
+    };
+
+    bless_annotations(c.clone(), false).unwrap();
+    let path = dir.join("outdated.rs");
+    assert_eq!(read(&path), "\
+//! Blessed by `tests/annotate.rs`. The annotations are outdated on purpose.
+
+fn main() {
+    let ok = 0;
+    let () = ok;    //~ error[E0308]
+    let _x: u8 = \"not a number\";
+    //~^ error[E0308]
+    // fixed: let _x: u8 = 0;
+}
+");
+    run_single_test(c, path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn upgrade_turns_messages_into_codes() {
+    let dir = copy_to_temp_dir("tests/bless/message-patterns.rs");
+    let c = Config {
+        cfail_paths: vec![dir.clone()],
+        ..config()
+    };
+
+    upgrade_patterns(c.clone(), false).unwrap();
+    let path = dir.join("message-patterns.rs");
+    assert_eq!(read(&path), "\
+//! Upgraded by `tests/annotate.rs`.
+
+fn main() {
+    let () = 0; //~ error[E0308]
+    let _x = y;
+    //~^ error[E0425]
+}
+");
+    run_single_test(c, path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn scaffold_suggests_annotations() {
+    let scaffolded = scaffold(config(), PathBuf::from("tests/scaffold/unannotated.rs")).unwrap();
+    assert_eq!(scaffolded, "\
+fn main() {
+    let x = (0);
+    let () = x;
+    //~^ error[E0308]
+}
+note: tests/scaffold/unannotated.rs:2: warning[unused_parens]: unnecessary parentheses around assigned value \
+(warnings can't be matched by patterns; fix it, or set `strict-warnings = false` in compile-fail.toml)
+");
+}
+
+#[test]
+fn scaffold_warns_about_passing_tests() {
+    let scaffolded = scaffold(config(), PathBuf::from("tests/scaffold/compiles.rs")).unwrap();
+    assert_eq!(scaffolded, "\
+fn main() {}
+warning: tests/scaffold/compiles.rs compiled without errors, so it isn't a useful compile-fail test
+");
+}
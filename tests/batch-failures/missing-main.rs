@@ -0,0 +1,5 @@
+//! Has no `main`, so it fails on its own, and must not pass as part of a batch either.
+
+fn helper() {
+    let () = 0; //~ error: mismatched types
+}
@@ -0,0 +1,32 @@
+//! Runs the regular compile-fail tests in batch mode, which must not change the results.
+
+#[macro_use] extern crate compile_fail;
+
+use compile_fail::{Config, Error, try_run_tests};
+use std::path::PathBuf;
+
+run_compile_fail_tests!(Config {
+    cfail_paths: vec![
+        PathBuf::from("tests/compile-fail"),
+        PathBuf::from("tests/settings/lenient-warnings"),
+    ],
+    batch: true,
+    wrapper_test: file!(),
+    ..Config::default()
+});
+
+#[test]
+fn batch_keeps_missing_main_error() {
+    let c = Config {
+        cfail_paths: vec![PathBuf::from("tests/batch-failures")],
+        batch: true,
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    };
+
+    match try_run_tests(c) {
+        Err(Error::TestsFailed(ref failures)) if failures.len() == 1 => {}
+        other => panic!("expected missing-main.rs to fail, got {:?}", other),
+    }
+}
@@ -0,0 +1,7 @@
+//! Upgraded by `tests/annotate.rs`.
+
+fn main() {
+    let () = 0; //~ error: mismatched types
+    let _x = y;
+    //~^ error: cannot find value `y` in this scope
+}
@@ -0,0 +1,10 @@
+//! Blessed by `tests/annotate.rs`. The annotations are outdated on purpose.
+
+fn main() {
+    let ok = 0; //~ error[E0308]
+    // fixed: let ok = ();
+    let () = ok;    //~ error: this message was reworded
+    let _x: u8 = "not a number";
+    //~^ error: mismatched types
+    // fixed: let _x: u8 = 0;
+}
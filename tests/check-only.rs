@@ -0,0 +1,11 @@
+//! Runs the regular compile-fail tests without codegen, which must not change the results.
+
+#[macro_use] extern crate compile_fail;
+
+use compile_fail::Config;
+
+run_compile_fail_tests!(Config {
+    check_only: true,
+    wrapper_test: file!(),
+    ..Config::default()
+});
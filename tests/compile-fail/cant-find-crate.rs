@@ -1 +1,3 @@
 extern crate doesntexist;   //~ error: can't find crate
+
+fn main() {}
@@ -0,0 +1,9 @@
+// check-pass
+//! The accepted counterpart of a compile-fail test, proving that the API can be used correctly.
+
+fn main() {
+    let _r = {
+        let local = 5;
+        local
+    };
+}
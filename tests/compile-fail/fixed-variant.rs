@@ -0,0 +1,7 @@
+//! A `// fixed:` comment shows how to make the errors on the line above it go away. When
+//! `Config::verify_fixes` is enabled, the test is compiled again with the line replaced.
+
+fn main() {
+    let _x: u8 = "not a number";  //~ error: mismatched types
+    // fixed: let _x: u8 = 0;
+}
@@ -0,0 +1,5 @@
+// ignore: demonstrates the `ignore` directive, this test only runs with `--ignored`
+
+fn main() {
+    let _x: u8 = "not a number";  //~ error: mismatched types
+}
@@ -0,0 +1,5 @@
+// known-bug: demonstrates the `known-bug` directive, this compiles fine
+
+fn main() {
+    let _x: u8 = 0;  //~ error: mismatched types
+}
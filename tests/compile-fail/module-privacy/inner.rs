@@ -0,0 +1,8 @@
+pub struct Token(());
+
+impl Token {
+    pub fn new() -> Self {
+        let () = Token(()); //~ error: mismatched types
+        Token(())
+    }
+}
@@ -0,0 +1,9 @@
+//! A directory with a `main.rs` is a single test made up of multiple files. Patterns can be placed
+//! in any of them.
+
+mod inner;
+
+fn main() {
+    inner::Token::new();
+    inner::Token(());   //~ error: is private
+}
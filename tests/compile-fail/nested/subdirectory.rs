@@ -0,0 +1,5 @@
+//! Tests in subdirectories are found too. This one is named `nested/subdirectory.rs`.
+
+fn main() {
+    let () = 0; //~ error: mismatched types
+}
@@ -0,0 +1,6 @@
+// normalize-stderr: "mismatched (\w+)" -> "$1 mismatch"
+//! Normalization rules are applied to messages before they are matched by patterns.
+
+fn main() {
+    let () = 0; //~ error: types mismatch
+}
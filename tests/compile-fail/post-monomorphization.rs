@@ -0,0 +1,21 @@
+// build-fail
+//
+// This error is only emitted during monomorphization, so the test must always be fully built.
+
+struct Zst<T>(T);
+
+impl<T> Zst<T> {
+    // Indexes out of bounds for every `T` that isn't zero-sized
+    const SIZE_CHECK: () = [()][std::mem::size_of::<T>()];
+    //~^ error[E0080]
+    // fixed: const SIZE_CHECK: () = ();
+
+    fn new(value: T) -> Self {
+        let () = Self::SIZE_CHECK;
+        Zst(value)
+    }
+}
+
+fn main() {
+    Zst::new(0u8);
+}
@@ -0,0 +1,9 @@
+// strict-primary-error
+//! With `strict-primary-error`, the first pattern must match the first error rustc reports, so
+//! that a change in the root cause of the failure doesn't go unnoticed.
+
+fn main() {
+    let _x: u8 = "not a number";  //~ error: mismatched types
+    // fixed: let _x: u8 = 0;
+    let _y: u16 = _x;             //~ error: mismatched types
+}
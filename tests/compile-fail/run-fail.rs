@@ -0,0 +1,12 @@
+// run-fail
+// exit-code: 101
+// panic-message: already borrowed
+//! Run-fail tests check runtime invariants that the type system can't enforce.
+
+use std::cell::RefCell;
+
+fn main() {
+    let cell = RefCell::new(0);
+    let _first = cell.borrow_mut();
+    let _second = cell.borrow_mut();
+}
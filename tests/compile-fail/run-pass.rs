@@ -0,0 +1,7 @@
+// run-pass
+//! Run-pass tests are built and executed, and must exit successfully.
+
+fn main() {
+    let v = vec![1, 2, 3];
+    assert_eq!(v.iter().sum::<i32>(), 6);
+}
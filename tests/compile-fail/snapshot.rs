@@ -0,0 +1,5 @@
+//! The rendered output of this test must match `snapshot.stderr`.
+
+fn main() {
+    let () = 0; //~ error[E0308]
+}
@@ -11,13 +11,20 @@ fn failures() {
     let path = PathBuf::from("tests/failures");
 
     let c = Config {
-        cfail_path: path.clone(),
+        cfail_paths: vec![path.clone()],
         wrapper_test: file!(),
         no_console_output: true,
+        // Needed by `unrelated-error-on-line.rs`. It doesn't affect tests that fail anyway.
+        verify_fixes: true,
+        ..Config::default()
     };
 
     for entry in read_dir(&path).unwrap() {
         let entry = entry.unwrap();
+        if entry.path().extension().map_or(false, |ext| ext == "stderr") {
+            // Snapshots belong to the test next to them
+            continue;
+        }
 
         match run_single_test(c.clone(), entry.path().to_owned()) {
             Ok(()) => panic!("test {} succeeded, but was expected to fail", entry.path().display()),
@@ -33,9 +40,10 @@ fn failures() {
 #[should_panic(expected = "couldn't open this-dir/does-not-exist")]
 fn no_such_dir() {
     let c = Config {
-        cfail_path: "this-dir/does-not-exist".into(),
+        cfail_paths: vec!["this-dir/does-not-exist".into()],
         wrapper_test: file!(),
         no_console_output: true,
+        ..Config::default()
     };
 
     run_tests(c);
@@ -45,10 +53,93 @@ fn no_such_dir() {
 #[should_panic(expected = "no compile-fail test found in tests/empty")]
 fn empty_dir() {
     let c = Config {
-        cfail_path: "tests/empty".into(),
+        cfail_paths: vec!["tests/empty".into()],
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    };
+
+    run_tests(c);
+}
+
+#[test]
+#[should_panic(expected = "no compile-fail test found in tests/compile-fail")]
+fn everything_excluded() {
+    let c = Config {
         wrapper_test: file!(),
         no_console_output: true,
+        exclude: vec!["*".to_string()],
+        ..Config::default()
     };
 
     run_tests(c);
 }
+
+#[test]
+#[should_panic(expected = "tests/failures/invalid-annotation.rs:4:22: 'eror' is an invalid message kind")]
+fn invalid_annotation() {
+    let c = Config {
+        cfail_paths: vec!["tests/failures".into()],
+        include: vec!["invalid-annotation.rs".to_string()],
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    };
+
+    run_tests(c);
+}
+
+#[test]
+fn structured_mismatches() {
+    let c = Config {
+        cfail_paths: vec!["tests/failures".into()],
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    };
+
+    let path = PathBuf::from("tests/failures/wrong-line.rs");
+    match run_single_test(c, path.clone()) {
+        Err(Error::TestsFailed(failures)) => {
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].mismatches, vec![
+                Mismatch::Missing {
+                    file: path.clone(),
+                    line: 3,
+                    pattern: "error: mismatched types".to_string(),
+                },
+                Mismatch::Unexpected {
+                    file: path.clone(),
+                    line: 4,
+                    message: "error[E0308]: mismatched types".to_string(),
+                },
+            ]);
+        }
+        other => panic!("expected a test failure, got {:?}", other),
+    }
+}
+
+#[test]
+fn structured_annotation_errors() {
+    let c = Config {
+        cfail_paths: vec!["tests/failures".into()],
+        include: vec!["invalid-annotation.rs".to_string()],
+        wrapper_test: file!(),
+        no_console_output: true,
+        ..Config::default()
+    };
+
+    match try_run_tests(c) {
+        Err(Error::TestsFailed(failures)) => {
+            assert_eq!(failures.len(), 1);
+            assert!(failures[0].mismatches.is_empty());
+            assert_eq!(failures[0].annotation_errors, vec![AnnotationError {
+                file: PathBuf::from("tests/failures/invalid-annotation.rs"),
+                line: 4,
+                column: 22,
+                message: "'eror' is an invalid message kind".to_string(),
+            }]);
+        }
+        other => panic!("expected a test failure, got {:?}", other),
+    }
+}
@@ -0,0 +1,6 @@
+// check-pass
+//! A check-pass test must not produce errors.
+
+fn main() {
+    let () = 0;
+}
@@ -0,0 +1,6 @@
+// check-pass
+//! Tests that must compile can't have error patterns.
+
+fn main() {
+    let () = 0;  //~ error: mismatched types
+}
@@ -0,0 +1,7 @@
+//! Removing the annotated line leaves `x` undeclared, so the variant proves nothing about the
+//! pattern. Verifying the fix reports this instead of passing.
+
+fn main() {
+    let x: u8 = "not a number";  //~ error: mismatched types
+    let _y: u8 = x;
+}
@@ -0,0 +1,5 @@
+//! Misspelled message kind in a pattern.
+
+fn main() {
+    let () = 0;  //~ eror: mismatched types
+}
@@ -0,0 +1,8 @@
+// strict-primary-error
+//! The first pattern doesn't refer to the first error rustc reports.
+
+fn main() {
+    let () = 0;
+    let _x: u8 = "not a number";  //~ error: mismatched types
+    //~^^ error: mismatched types
+}
@@ -0,0 +1,4 @@
+// run-fail
+//! A run-fail test must not exit successfully.
+
+fn main() {}
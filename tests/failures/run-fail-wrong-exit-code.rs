@@ -0,0 +1,7 @@
+// run-fail
+// exit-code: 3
+//! The exit code must match.
+
+fn main() {
+    std::process::exit(1);
+}
@@ -0,0 +1,11 @@
+// run-fail
+// panic-message: already mutably borrowed
+//! The panic message must match.
+
+use std::cell::RefCell;
+
+fn main() {
+    let cell = RefCell::new(0);
+    let _first = cell.borrow_mut();
+    let _second = cell.borrow_mut();
+}
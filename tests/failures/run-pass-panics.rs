@@ -0,0 +1,6 @@
+// run-pass
+//! A run-pass test must exit successfully.
+
+fn main() {
+    panic!("run-pass test panicked");
+}
@@ -0,0 +1,5 @@
+//! The patterns match, but the rendered output differs from `snapshot-mismatch.stderr`.
+
+fn main() {
+    let () = 0; //~ error: mismatched types
+}
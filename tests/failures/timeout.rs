@@ -0,0 +1,7 @@
+//! The compiler can't possibly finish in time, so this test is killed and times out.
+
+// timeout: 1ms
+
+fn main() {
+    let () = 0; //~ error: mismatched types
+}
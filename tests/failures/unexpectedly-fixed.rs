@@ -0,0 +1,5 @@
+// known-bug: #1
+
+fn main() {
+    let _x: u8 = "not a number";  //~ error: mismatched types
+}
@@ -0,0 +1,3 @@
+pub fn f() {
+    let () = 0;
+}
@@ -0,0 +1,8 @@
+//! The error in `inner.rs` isn't matched by any pattern.
+
+mod inner;
+
+fn main() {
+    let () = 0; //~ error: mismatched types
+    inner::f();
+}
@@ -0,0 +1,7 @@
+//! The pattern is meant for the first statement, but also matches the error in the second one.
+//! Verifying the fix reveals this.
+
+fn main() {
+    let _x: u8 = "not a number"; let () = 0u8;  //~ error: mismatched types
+    // fixed: let _x: u8 = 0; let () = 0u8;
+}
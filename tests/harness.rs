@@ -0,0 +1,6 @@
+//! Runs the compile-fail tests without the default test harness, so that every compile-fail test
+//! is a separate test (try `cargo test --test harness -- --list`).
+
+#[macro_use] extern crate compile_fail;
+
+compile_fail_harness!();
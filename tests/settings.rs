@@ -0,0 +1,15 @@
+//! Runs tests from multiple directories, each with its own `compile-fail.toml`.
+
+#[macro_use] extern crate compile_fail;
+
+use compile_fail::Config;
+use std::path::PathBuf;
+
+run_compile_fail_tests!(Config {
+    cfail_paths: vec![
+        PathBuf::from("tests/settings/edition-2018"),
+        PathBuf::from("tests/settings/lenient-warnings"),
+    ],
+    wrapper_test: file!(),
+    ..Config::default()
+});
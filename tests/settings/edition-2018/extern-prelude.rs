@@ -0,0 +1,7 @@
+//! Dependencies are in scope without `extern crate` in the 2018 edition.
+
+use either::Either;
+
+fn main() {
+    let () = Either::Left::<i32, i32>(0);   //~ error: mismatched types
+}
@@ -0,0 +1,6 @@
+//! Warnings don't need a pattern when `strict-warnings` is disabled.
+
+fn main() {
+    let unused = 0;
+    let () = 0; //~ error: mismatched types
+}
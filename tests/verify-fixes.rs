@@ -0,0 +1,11 @@
+//! Runs the regular compile-fail tests again, checking that each pattern is caused by its line.
+
+#[macro_use] extern crate compile_fail;
+
+use compile_fail::Config;
+
+run_compile_fail_tests!(Config {
+    verify_fixes: true,
+    wrapper_test: file!(),
+    ..Config::default()
+});